@@ -0,0 +1,47 @@
+// Stepping throughput on examples/count-to-32767.obj, run with `cargo bench`.
+// Measured with and without the decoded instruction cache.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use lc3::io::{AssemblyInfo, read_file};
+use lc3::vm::machine::Machine;
+
+const RUNS: u32 = 50;
+
+fn main() {
+    let program = read_file(Path::new("examples/count-to-32767.obj"));
+
+    bench("decode cache off", &program, false);
+    bench("decode cache on", &program, true);
+}
+
+fn bench(name: &str, program: &AssemblyInfo, decode_cache: bool) {
+    let mut instructions = 0;
+    let mut best = Duration::MAX;
+    let mut total = Duration::ZERO;
+
+    for _ in 0..RUNS {
+        let mut machine = Machine::new(0x3000, true, true, &[]);
+        machine.memory.set_decode_cache(decode_cache);
+        machine.load_assembly(program);
+
+        let start = Instant::now();
+        machine.run_until_halt();
+        let elapsed = start.elapsed();
+
+        instructions = machine.instruction_count;
+        best = best.min(elapsed);
+        total += elapsed;
+    }
+
+    let mips = |time: Duration| instructions as f64 / time.as_secs_f64() / 1_000_000.0;
+
+    println!("count-to-32767, {name}: {instructions} instructions per run, {RUNS} runs");
+    println!("  best: {best:?} ({:.1} MIPS)", mips(best));
+    println!(
+        "  mean: {:?} ({:.1} MIPS)",
+        total / RUNS,
+        mips(total / RUNS)
+    );
+}
@@ -78,6 +78,12 @@ impl Lc3ToolsCodegen {
                 AstNode::Fill(val) => {
                     self.write(&format!("{}\n", num_to_4_hexadecimal(val as u16)))
                 }
+                AstNode::FillLabel(label) => {
+                    let Some(&address) = self.label_lookup.get(&label) else {
+                        panic!("label {label} used by .fill does not exist");
+                    };
+                    self.write(&format!("{}\n", num_to_4_hexadecimal(address as u16)))
+                }
                 AstNode::Stringz(phrase) => {
                     let bytes = phrase.bytes();
 
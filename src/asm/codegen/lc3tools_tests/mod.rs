@@ -18,4 +18,39 @@ pub mod tests {
 
         assert_eq!(String::from_utf8(output.bytes).unwrap(), EXPECTED);
     }
+
+    #[test]
+    fn test_basic_os() {
+        let tokenizer = Tokenizer::new(lc3::vm::os::BASIC_OS_SOURCE);
+        let tokens = tokenizer.tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let output = Lc3ToolsCodegen::new().generate(ast);
+
+        assert_eq!(output.bytes, lc3::vm::os::BASIC_OS_IMAGE);
+    }
+
+    #[test]
+    fn test_fill_label() {
+        let source =
+            ".orig x0020\n.fill TARGET\n.fill x1F\n.end\n.orig x0200\nRET\nTARGET RET\n.end\n";
+
+        let tokens = Tokenizer::new(source).tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let output = Lc3ToolsCodegen::new().generate(ast);
+        let output = String::from_utf8(output.bytes).unwrap();
+
+        assert!(output.contains("0020\n2\n0201\n001F\n"));
+    }
+
+    #[test]
+    fn test_trap_aliases() {
+        let source = ".orig x3000\nGETC\nOUT\nPUTS\nIN\nPUTSP\nHALT\n.end\n";
+
+        let tokens = Tokenizer::new(source).tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let output = Lc3ToolsCodegen::new().generate(ast);
+        let output = String::from_utf8(output.bytes).unwrap();
+
+        assert!(output.contains("3000\n6\nF020\nF021\nF022\nF023\nF024\nF025\n"));
+    }
 }
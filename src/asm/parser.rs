@@ -75,6 +75,7 @@ pub enum AstNode {
     Label(String),
 
     Fill(i16),
+    FillLabel(String),
     Stringz(String),
     Blkw(u16),
 }
@@ -93,7 +94,7 @@ impl AstNode {
             }
             AstNode::Instruction(_) => 1,
             AstNode::Label(_) => 0,
-            AstNode::Fill(_) => 1,
+            AstNode::Fill(_) | AstNode::FillLabel(_) => 1,
             AstNode::Stringz(str) => str.len() + 1, // + null terminator, each char gets it's own word
             AstNode::Blkw(size) => *size as usize,
         }
@@ -161,6 +162,7 @@ impl Parser {
                         Token::Instruction(opcode) => self.parse_instruction(opcode, &next)?,
 
                         Token::Fill(val) => AstNode::Fill(*val),
+                        Token::FillLabel(label) => AstNode::FillLabel(label.clone()),
                         Token::Blkw(val) => AstNode::Blkw(*val),
                         Token::Stringz(val) => AstNode::Stringz(val.clone()),
 
@@ -5,7 +5,7 @@ use core::panic;
 const INSTRUCTIONS: &[&str] = &[
     "add", "and", "brn", "brnz", "brnzp", "brz", "brzp", "brp", "brnz", "brnp", "jmp", "jsr",
     "jsrr", "ld", "ldi", "ldr", "lea", "not", "ret", "rti", "st", "sti", "str", "trap", "getc",
-    "puts", "in", "out", "halt", // trap vector convienences
+    "puts", "in", "out", "putsp", "halt", // trap vector convienences
 ];
 
 // for some reason the Try trait is still 'experimental', so in order to implement
@@ -88,6 +88,7 @@ pub enum Token {
     Origin(u16),
     End,
     Fill(i16),
+    FillLabel(String),
     Blkw(u16),
     Stringz(String),
     Label(String),
@@ -253,7 +254,18 @@ impl<'a> Tokenizer<'a> {
 
                 ".fill" => {
                     let word = tryit!(self.consume_word()).to_string();
-                    let index = tryit!(self.read_next_i16_num(&word));
+
+                    // anything that isn't a number is a label, filled in with its address
+                    if !word.is_empty() && !is_number_literal(&word) {
+                        return TokenizerResult::Ok(Token::FillLabel(word.to_lowercase()));
+                    }
+
+                    // hex is a raw bit pattern, so the whole x0000..=xFFFF range is allowed
+                    let index = if word.starts_with('x') {
+                        tryit!(self.read_next_u16_bit_num(&word)) as i16
+                    } else {
+                        tryit!(self.read_next_i16_num(&word))
+                    };
                     TokenizerResult::Ok(Token::Fill(index))
                 }
 
@@ -424,3 +436,12 @@ impl<'a> Tokenizer<'a> {
         // TokenizerResult::Ok(&self.source[(start + 1)..(self.pointer - 1)])
     }
 }
+
+// x followed by hex digits is a number, so x1F is a number but xyz is a label
+fn is_number_literal(word: &str) -> bool {
+    match word.chars().next() {
+        Some('x') => word.len() > 1 && word[1..].chars().all(|c| c.is_ascii_hexdigit()),
+        Some(c) => c.is_ascii_digit() || c == '#' || c == '-',
+        None => false,
+    }
+}
@@ -73,3 +73,13 @@ pub fn check_i5_range(x: i8) {
 pub fn check_i11_range(x: i16) {
     assert!((-1024..=1023).contains(&x))
 }
+
+// splitmix64, small and fast. not for cryptography, but plenty for filling memory
+pub fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
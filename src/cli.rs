@@ -3,18 +3,23 @@
 mod asm;
 mod cli_tools;
 
-use crossterm::event::{KeyCode, KeyModifiers};
 use crossterm::style::Stylize;
 use lc3::io;
+use lc3::vm::console::{Console, TerminalConsole};
+use lc3::vm::debugger::StopReason;
+use lc3::vm::devices::{
+    BlockDevice, Clock, ClockMode, DEFAULT_INSTRUCTIONS_PER_SECOND, OverflowPolicy, Rng,
+    ToneGenerator, Video,
+};
 use lc3::vm::machine::*;
+use lc3::vm::trace::TraceFormat;
 // use vm::machine::*;
 
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
-use std::time::Duration;
-
-use lc3::io::AssemblyInfo;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[cfg(feature = "asm")]
 use crate::asm::codegen::Codegen;
@@ -28,6 +33,13 @@ use crate::cli_tools::get_flag;
 #[cfg(feature = "asm")]
 use crate::cli_tools::get_param;
 
+// exit codes of `run` when it stops the program before it halts.
+// errors returned from `main`, like a missing disk image, exit with 1
+const EXIT_INSTRUCTION_LIMIT: i32 = 2;
+const EXIT_TIMEOUT: i32 = 3;
+// bad arguments, EX_USAGE from sysexits.h
+const EXIT_USAGE: i32 = 64;
+
 fn main() -> std::io::Result<()> {
     let args: Vec<String> = std::env::args().collect();
 
@@ -53,7 +65,24 @@ fn main() -> std::io::Result<()> {
                 "
 lc3-cli help
 Subcommands:
-    run <path>\t\t\t Run a assembled object file for the LC-3.
+    run <path> [--os <os_path>] [--rng] [--seed <seed>] [--clock] [--virtual-clock [rate]] [--randomize-memory [seed]] [--input <text>] [--type-ahead <n> [--drop-oldest]] [--check-uninitialized] [--trace <file> [--trace-format text|json]] [--resume <snapshot>] [--checkpoint <snapshot>] [--max-instructions <n>] [--timeout <seconds>] [--disk <image>] [--frame <file.png|ppm> [--frame-every <n>]] [--wav <file>]\t Run a assembled object file for the LC-3.
+        --os boots from the given object file instead of the basic OS.
+        --rng enables the random number register at xFE26, seeded from the current time.
+        --seed enables it with the given seed, which also seeds --randomize-memory when it isn't given a seed itself.
+        --clock enables the instruction counter at xFE28 and xFE2A and the real time clock at xFE2C and xFE2E.
+        --virtual-clock enables them with the clock counting instructions instead of real time, at 10000 per second unless a rate is given.
+        --randomize-memory fills uninitialized memory with random values, the same seed gives the same values.
+        --input types the text ahead of time, keys pressed during the run follow it.
+        --type-ahead sets how many keys wait for the program to read them, 256 by default.
+            Once full, new keys are dropped, or the oldest waiting key with --drop-oldest.
+        --check-uninitialized warns about the program reading registers or memory it never wrote.
+        --trace writes a record of every executed instruction, as text or JSON lines.
+        --resume continues from a saved snapshot, --checkpoint saves one when stopped with Ctrl+C.
+        --disk attaches a block device backed by the image file, in sectors of 256 words at xFE14 to xFE1A.
+        --frame enables the 128x124 video memory at xC000 and saves it as an image when the program stops,
+            --frame-every also saves numbered frames every n instructions.
+        --wav enables the tone generator at xFE20 to xFE24 and renders what it played to a WAV file, at 10000 instructions per second.
+        --max-instructions and --timeout stop a program that runs too long, exiting with code 2 and 3 respectively. Invalid arguments exit with code 64.
     asm <path> [--verbose|-v] [--output|-o <output_path>]\t Assemble an LC-3 assembly file into an object file compatible with lc3tools.
                 "
             );
@@ -175,47 +204,239 @@ fn asm_file(path: &str, args: &[&str]) -> std::io::Result<()> {
     Ok(())
 }
 
+fn time_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+// PNG or PPM, depending on the extension
+fn write_frame(machine: &Machine, path: &Path) -> std::io::Result<()> {
+    let frame = machine.frame().expect("video device is attached");
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+    {
+        frame.write_png(&mut writer)?;
+    } else {
+        frame.write_ppm(&mut writer)?;
+    }
+    writer.flush()
+}
+
+// frame.png => frame-1000.png
+fn numbered_frame_path(path: &str, instruction_count: u64) -> PathBuf {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let mut name = format!("{stem}-{instruction_count}");
+    if let Some(ext) = path.extension() {
+        name = format!("{name}.{}", ext.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message.red());
+    std::process::exit(EXIT_USAGE);
+}
+
+fn parse_param<T: FromStr>(value: &str, message: &str) -> T {
+    value.parse().unwrap_or_else(|_| usage_error(message))
+}
+
 fn run_file(path: &str, args: &[&str]) -> std::io::Result<()> {
-    let AssemblyInfo { data } = io::read_file(Path::new(path));
+    let info = io::read_file(Path::new(path));
 
     let ip = cli_tools::get_param(args, "pc", None).unwrap_or("3000".to_string());
-    let ip = u16::from_str_radix(&ip, 16)
-        .expect("Invalid hex for starting instruction pointer/program counter position.");
+    let ip = u16::from_str_radix(&ip, 16).unwrap_or_else(|_| {
+        usage_error("Invalid hex for starting instruction pointer/program counter position.")
+    });
+
+    let mut machine = match cli_tools::get_param(args, "os", None) {
+        Some(os) => {
+            let mut machine = Machine::new_without_os(ip, true, true, &[]);
+            machine.load_os(&io::read_file(Path::new(&os)));
+            machine
+        }
+        None => Machine::new(ip, true, true, &[]),
+    };
+
+    let seed = cli_tools::get_param(args, "seed", None)
+        .map(|seed| parse_param::<u64>(&seed, "--seed expects a number."));
+
+    if let Some(pos) = cli_tools::get_position(args, "randomize-memory", None) {
+        // the seed is optional, without one a new one is picked (and shown, so the run can be repeated)
+        let seed = match args
+            .get(pos + 1)
+            .and_then(|seed| seed.parse::<u64>().ok())
+            .or(seed)
+        {
+            Some(seed) => seed,
+            None => {
+                let seed = time_seed();
+                println!("Randomizing memory with seed {seed}");
+                seed
+            }
+        };
+
+        machine.set_memory_init(MemoryInit::Random(seed));
+    }
+
+    machine.load_assembly(&info);
+
+    if let Some(disk) = cli_tools::get_param(args, "disk", None) {
+        machine.attach_device(BlockDevice::open(disk)?);
+    }
+
+    let frame = cli_tools::get_param(args, "frame", None);
+    let frame_every = cli_tools::get_param(args, "frame-every", None).map(|every| {
+        let message = "--frame-every expects a number of instructions.";
+        match parse_param::<u64>(&every, message) {
+            0 => usage_error(message),
+            every => every,
+        }
+    });
+    if frame.is_some() {
+        machine.attach_device(Video);
+    }
+
+    if seed.is_some() || get_flag(args, "rng", None) {
+        machine.attach_device(Rng::new(seed.unwrap_or_else(time_seed)));
+    }
 
-    let mut machine = Machine::new(ip, true, true, &[]);
+    // the rate is optional
+    let clock = match cli_tools::get_position(args, "virtual-clock", None) {
+        Some(pos) => Some(ClockMode::Virtual {
+            instructions_per_second: args
+                .get(pos + 1)
+                .and_then(|rate| rate.parse::<u64>().ok())
+                .unwrap_or(DEFAULT_INSTRUCTIONS_PER_SECOND),
+        }),
+        None if get_flag(args, "clock", None) => Some(ClockMode::Wall),
+        None => None,
+    };
+    if let Some(clock) = clock {
+        machine.attach_device(Clock::new(clock));
+    }
+
+    let wav = cli_tools::get_param(args, "wav", None);
+    if wav.is_some() {
+        machine.attach_device(ToneGenerator::default());
+    }
+
+    if let Some(snapshot) = cli_tools::get_param(args, "resume", None) {
+        machine.load_snapshot(BufReader::new(File::open(snapshot)?))?;
+    }
+
+    let checkpoint = cli_tools::get_param(args, "checkpoint", None);
+
+    if let Some(capacity) = cli_tools::get_param(args, "type-ahead", None) {
+        let capacity = parse_param::<usize>(&capacity, "--type-ahead expects a number of keys.");
+        let overflow = if get_flag(args, "drop-oldest", None) {
+            OverflowPolicy::DropOldest
+        } else {
+            OverflowPolicy::DropNewest
+        };
+        machine.set_type_ahead(capacity, overflow);
+    }
+
+    if let Some(input) = cli_tools::get_param(args, "input", None) {
+        machine.preload_input(input.as_bytes());
+    }
 
-    for datum in data {
-        machine.set_span_at(datum.orig, &datum.data);
+    if get_flag(args, "check-uninitialized", None) {
+        machine.enable_init_checks();
     }
 
-    crossterm::terminal::enable_raw_mode()?;
+    if let Some(trace) = cli_tools::get_param(args, "trace", None) {
+        let format = match cli_tools::get_param(args, "trace-format", None).as_deref() {
+            None | Some("text") => TraceFormat::Text,
+            Some("json") => TraceFormat::JsonLines,
+            Some(other) => {
+                usage_error(&format!("Unknown trace format '{other}', expected text or json."))
+            }
+        };
+
+        machine.start_trace(BufWriter::new(File::create(trace)?), format);
+    }
+
+    if let Some(max) = cli_tools::get_param(args, "max-instructions", None) {
+        let max = parse_param::<u64>(&max, "--max-instructions expects a number of instructions.");
+        machine.debugger.instruction_limit = Some(machine.instruction_count.saturating_add(max));
+    }
+    if let Some(timeout) = cli_tools::get_param(args, "timeout", None) {
+        let message = "--timeout expects a number of seconds.";
+        let timeout = Duration::try_from_secs_f64(parse_param::<f64>(&timeout, message))
+            .unwrap_or_else(|_| usage_error(message));
+        // too far in the future to ever be reached
+        machine.debugger.deadline = Instant::now().checked_add(timeout);
+    }
+
+    let mut console = TerminalConsole::new()?;
+    let mut reason = None;
 
     while !machine.halted {
-        if crossterm::event::poll(Duration::ZERO)? {
-            let event = crossterm::event::read()?;
-
-            if let Some(key_event) = event.as_key_event() {
-                if key_event.code == KeyCode::Char('c')
-                    && key_event.modifiers.contains(KeyModifiers::CONTROL)
-                {
-                    break;
-                } else if let Some(char) = key_event.code.as_char() {
-                    machine.set_keyboard_key(char as u16);
-                }
+        reason = machine.limit_reached();
+        if reason.is_some() {
+            break;
+        }
+
+        if console.stop_requested()? {
+            if let Some(checkpoint) = &checkpoint {
+                let mut writer = BufWriter::new(File::create(checkpoint)?);
+                machine.save_snapshot(&mut writer)?;
+                writer.flush()?;
             }
+            break;
         }
 
-        if let Some(data) = machine.poll_display_data() {
-            crossterm::terminal::disable_raw_mode()?; // lol, maybe dont do this?
-            print!("{}", data as u8 as char);
-            crossterm::terminal::enable_raw_mode()?;
-            std::io::stdout().flush()?;
+        machine.step_with_console(&mut console)?;
+
+        if let (Some(frame), Some(every)) = (&frame, frame_every)
+            && machine.instruction_count.is_multiple_of(every)
+        {
+            write_frame(
+                &machine,
+                &numbered_frame_path(frame, machine.instruction_count),
+            )?;
         }
 
-        machine.step();
+        for diagnostic in machine.take_diagnostics() {
+            console.suspend(|| eprintln!("{}", format!("warning: {diagnostic}").yellow()))?;
+        }
     }
 
-    crossterm::terminal::disable_raw_mode()?;
+    // the last characters of the halting message
+    machine.flush_console(&mut console)?;
+    drop(console);
 
-    Ok(())
+    machine.stop_trace()?;
+
+    if let Some(frame) = &frame {
+        write_frame(&machine, Path::new(frame))?;
+    }
+
+    if let Some(wav) = wav
+        && let Some(generator) = machine.device::<ToneGenerator>()
+    {
+        let mut writer = BufWriter::new(File::create(wav)?);
+        generator.write_wav(&mut writer)?;
+        writer.flush()?;
+    }
+
+    match reason {
+        Some(StopReason::InstructionLimit) => {
+            eprintln!("{}", "Stopped: instruction limit reached".red());
+            std::process::exit(EXIT_INSTRUCTION_LIMIT);
+        }
+        Some(StopReason::Deadline) => {
+            eprintln!("{}", "Stopped: timed out".red());
+            std::process::exit(EXIT_TIMEOUT);
+        }
+        _ => Ok(()),
+    }
 }
@@ -1,4 +1,4 @@
-use std::{fs::File, io::Read, path::Path};
+use std::{collections::HashMap, fs::File, io::Read, path::Path};
 
 pub mod read_complex;
 pub mod read_raw;
@@ -9,12 +9,20 @@ const LC3_OBJ_HEADER: &[u8] = b"LC-3 OBJ FILE";
 pub struct DataInfo {
     pub orig: u16,
     pub data: Vec<i16>,
+
+    // indices into `data` of words left uninitialized (`????`, from .BLKW).
+    // their value in `data` is meaningless, the machine's `MemoryInit` decides it
+    pub uninitialized: Vec<usize>,
 }
 
 #[derive(Debug)]
 pub struct AssemblyInfo {
     // TODO, debug info, linker info, etc
     pub data: Vec<DataInfo>,
+
+    // label (lowercased, lc3tools writes them as they appear in the source) to address,
+    // from the .SYMBOL section
+    pub symbols: HashMap<String, u16>,
 }
 
 pub fn read_file(path: &Path) -> AssemblyInfo {
@@ -28,6 +36,7 @@ pub fn read_file(path: &Path) -> AssemblyInfo {
         read_complex::read(&buf)
     } else {
         println!("File missing header, interpreting as a raw file.\n");
+        #[allow(deprecated)]
         read_raw::read(&buf)
     }
 }
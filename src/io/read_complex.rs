@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use crate::io::{AssemblyInfo, DataInfo};
 
 enum ObjectFileSection {
@@ -27,6 +29,7 @@ pub fn read(data: &[u8]) -> AssemblyInfo {
     let mut section = ObjectFileSection::None;
 
     let mut data_sections: Vec<DataInfo> = vec![];
+    let mut symbols = HashMap::new();
 
     let lines: Vec<&str> = data.lines().collect();
 
@@ -44,15 +47,15 @@ pub fn read(data: &[u8]) -> AssemblyInfo {
         if line.starts_with(".") {
             section = get_section(&line);
         } else if !line.is_empty() {
-            #[allow(clippy::single_match)] // TODO read other sections
             match section {
                 ObjectFileSection::Text => {
                     if orig_length > 0 {
                         orig_length -= 1;
 
                         if line == "????" {
-                            // temporary fix. TODO, add option to randomize memory, instead of initializing it to 0.
-                            data_sections.last_mut().unwrap().data.push(0);
+                            let section = data_sections.last_mut().unwrap();
+                            section.uninitialized.push(section.data.len());
+                            section.data.push(0);
                             continue;
                         }
 
@@ -60,12 +63,26 @@ pub fn read(data: &[u8]) -> AssemblyInfo {
                         data_sections.last_mut().unwrap().data.push(val as i16);
                     } else {
                         let orig = u16::from_str_radix(&line, 16).unwrap();
-                        data_sections.push(DataInfo { orig, data: vec![] });
+                        data_sections.push(DataInfo {
+                            orig,
+                            data: vec![],
+                            uninitialized: vec![],
+                        });
                         orig_length = lines[i + 1].parse::<u16>().unwrap();
                         skip_next = true;
                     }
                 }
 
+                // ADDR | EXT | LABEL
+                ObjectFileSection::Symbol => {
+                    let columns: Vec<&str> = line.split('|').map(str::trim).collect();
+                    if let [addr, _, label] = columns[..]
+                        && let Ok(addr) = u16::from_str_radix(addr, 16)
+                    {
+                        symbols.insert(label.to_lowercase(), addr);
+                    }
+                }
+
                 // TODO
                 _ => (),
             }
@@ -73,14 +90,17 @@ pub fn read(data: &[u8]) -> AssemblyInfo {
     }
     AssemblyInfo {
         data: data_sections,
+        symbols,
     }
 }
 
+// the original tests predate running clippy on the test target
 #[cfg(test)] // TODO fix input with new keyboard system
+#[allow(clippy::unnecessary_cast, clippy::char_lit_as_u8)]
 mod tests {
     use crate::io::read_complex::read;
     use crate::tests;
-    use crate::vm::machine::Machine;
+    use crate::vm::machine::{HALT_MESSAGE, Machine};
 
     #[test]
     fn read_hello() {
@@ -228,7 +248,7 @@ LINE | ADDR | SOURCE
             out,
             format!(
                 "How many times (1 char please) (0..=9): {}",
-                "Hello, World!\n".repeat(5)
+                "Hello, World!\n".repeat(5) + HALT_MESSAGE
             )
         );
     }
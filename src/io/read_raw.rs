@@ -3,6 +3,7 @@
     note = "Will be replaced with a similar format that allows for more sections than just one .orig"
 )]
 
+use std::collections::HashMap;
 use std::io::Read;
 
 use crate::io::{AssemblyInfo, DataInfo};
@@ -34,6 +35,8 @@ pub fn read(mut data: &[u8]) -> AssemblyInfo {
         data: vec![DataInfo {
             orig: orig.unwrap(),
             data: res,
+            uninitialized: Vec::new(),
         }],
+        symbols: HashMap::new(),
     }
 }
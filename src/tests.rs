@@ -1,5 +1,33 @@
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::io::read_complex;
+use crate::vm::console::{BufferConsole, Console, ConsoleExit, PipeConsole};
+use crate::vm::debugger::{MemoryAccess, StopReason, WatchKind, WatchpointHit};
+use crate::vm::devices::block::{DISK_READ, DISK_WRITE, SECTOR_WORDS};
+use crate::vm::devices::display::{
+    DISPLAY_INTERRUPT_PRIORITY, DISPLAY_INTERRUPT_VECTOR, OUTPUT_CAPACITY,
+};
+use crate::vm::devices::keyboard::DEFAULT_TYPE_AHEAD;
+use crate::vm::devices::sound::{SAMPLE_RATE, Tone};
+use crate::vm::devices::timer::TIMER_INTERRUPT_VECTOR;
+use crate::vm::devices::video::{VIDEO_END, VIDEO_START};
+use crate::vm::devices::{
+    BlockDevice, CNTH, CNTL, Clock, DDR, DKBA, DKCR, DKSN, DKSR, DSR, Device, Display, KBDR, KBSR,
+    Keyboard, MCR, MPR, OverflowPolicy, RNDR, RTCM, RTCS, Rng, SNDD, SNDF, SNDS, TMI, TMR, Timer,
+    ToneGenerator, Video,
+};
+use crate::vm::diagnostics::{Diagnostic, UninitializedRead};
+use crate::vm::handle::{Event, MachineHandle, StopCause};
 use crate::vm::instructions::*;
-use crate::vm::machine::{Machine, PrivilegeMode};
+use crate::vm::interrupts::InterruptRequest;
+use crate::vm::machine::{
+    ConditionCode, HALT_MESSAGE, Lc3Error, Machine, Memory, MemoryInit, PrivilegeMode,
+};
+use crate::vm::os::{BASIC_OS_SSP, SAVED_SSP_LABEL};
+use crate::vm::trace::TraceFormat;
 
 #[test]
 fn add_instr() {
@@ -26,6 +54,22 @@ fn not_instr() {
     assert_eq!(format!("{not:016b}"), "1001000001111111");
 }
 
+#[test]
+fn disassembly() {
+    let cases = [
+        (0x1A62, "ADD R5, R1, #2"),
+        (0x0BFD, "BRnp #-3"),
+        (0xC1C0, "RET"),
+        (0x6E7F, "LDR R7, R1, #-1"),
+        (0xF025, "HALT"),
+        (0xF030, "TRAP x30"),
+    ];
+
+    for (word, text) in cases {
+        assert_eq!(Instruction::decode(word).to_string(), text);
+    }
+}
+
 #[test]
 fn add_add() {
     let mut machine = Machine::new_x3000(&[
@@ -91,9 +135,9 @@ fn print_a() {
         Instruction::trap_halt(),
     ]);
 
-    machine.run_until_halt();
+    let out = run_given_in_out(&mut machine, &[]);
 
-    assert_eq!(machine.get_display_data(), 'A' as u16);
+    assert_eq!(out, format!("A{HALT_MESSAGE}"));
 }
 
 #[test]
@@ -187,7 +231,54 @@ fn hello_world() {
     // machine.run_until_halt();
     let out = run_given_in_out(&mut machine, &[]);
 
-    assert_eq!(out, text);
+    assert_eq!(out, text.to_owned() + HALT_MESSAGE);
+}
+
+// two characters per word, low byte first
+fn pack_string(text: &str) -> Vec<i16> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| (pair[0] as u16 | (*pair.get(1).unwrap_or(&0) as u16) << 8) as i16)
+        .collect()
+}
+
+#[test]
+fn hello_world_putsp() {
+    let mut machine = Machine::new_x3000(&[
+        Instruction::LoadEffectiveAddress(Register::R0, 2.into()), // r0 = text_addr
+        Instruction::trap_putsp(), // print packed string stored at address in r0
+        Instruction::trap_halt(),
+    ]);
+
+    let text = "Hello, world!\n";
+    let text_addr = 0x3003;
+    let mut packed = pack_string(text);
+    packed.push(0); // even length, so terminate with a zero word
+    machine.set_span_at(text_addr, &packed);
+
+    let out = run_given_in_out(&mut machine, &[]);
+
+    assert_eq!(out, text.to_owned() + HALT_MESSAGE);
+    assert_eq!(machine.registers.get(Register::R0), text_addr as i16);
+}
+
+#[test]
+fn putsp_odd_length() {
+    let mut machine = Machine::new_x3000(&[
+        Instruction::AddImmediate(Register::R5, Register::R5, 3.into()),
+        Instruction::LoadEffectiveAddress(Register::R0, 2.into()),
+        Instruction::trap_putsp(),
+        Instruction::trap_halt(),
+    ]);
+
+    // the zero high byte of the last word ends the string
+    machine.set_span_at(0x3004, &pack_string("Hi!"));
+    machine.set_memory_at_unchecked(0x3006, 'x' as i16);
+
+    let out = run_given_in_out(&mut machine, &[]);
+
+    assert_eq!(out, format!("Hi!{HALT_MESSAGE}"));
+    assert_eq!(machine.registers.get(Register::R5), 3);
 }
 
 #[test]
@@ -360,7 +451,7 @@ fn hello_world_5() {
 
     let out = run_given_in_out(&mut machine, &[]);
 
-    assert_eq!(out, text.repeat(5));
+    assert_eq!(out, text.repeat(5) + HALT_MESSAGE);
 }
 
 #[test]
@@ -399,7 +490,7 @@ fn stacks() {
     assert_eq!(machine.stack_pop(), 30);
     assert_eq!(machine.stack_pop(), 10);
 
-    assert_eq!(machine.registers.get(Register::R6), 0x3000);
+    assert_eq!(machine.registers.get(Register::R6), BASIC_OS_SSP as i16);
     machine.set_privilege(PrivilegeMode::User);
     assert_eq!(machine.registers.get(Register::R6), (0xFE00u16) as i16);
 }
@@ -413,8 +504,8 @@ fn test_out() {
     ]);
 
     machine.set_memory_at_unchecked(0x3004, 'l' as i16);
-    machine.run_until_halt();
-    assert_eq!(machine.get_display_data(), 'l' as u16);
+    let out = run_given_in_out(&mut machine, &[]);
+    assert_eq!(out, format!("l{HALT_MESSAGE}"));
 }
 
 #[test]
@@ -422,7 +513,7 @@ fn test_reserved() {
     let mut machine = Machine::new_x3000(&[Instruction::Reserved, Instruction::trap_halt()]);
 
     let out = run_given_in_out(&mut machine, &[]);
-    assert_eq!(out, "[exc] Illegal opcode\n");
+    assert_eq!(out, format!("[exc] Illegal opcode\n{HALT_MESSAGE}"));
 }
 
 #[test]
@@ -431,7 +522,7 @@ fn test_invalid_privilege() {
         Machine::new_x3000(&[Instruction::ReturnFromInterrupt, Instruction::trap_halt()]);
 
     let out = run_given_in_out(&mut machine, &[]);
-    assert_eq!(out, "[exc] invalid privilege\n");
+    assert_eq!(out, format!("[exc] invalid privilege\n{HALT_MESSAGE}"));
 }
 
 #[test]
@@ -442,7 +533,7 @@ fn test_illegal_memory_access() {
     ]);
 
     let out = run_given_in_out(&mut machine, &[]);
-    assert_eq!(out, "[exc] ACV\n");
+    assert_eq!(out, format!("[exc] ACV\n{HALT_MESSAGE}"));
 }
 
 #[test]
@@ -466,6 +557,105 @@ fn test_machine_control_register() {
     assert_eq!(machine.halted, true);
 }
 
+#[test]
+fn halt_routine() {
+    let mut machine = Machine::new_x3000(&[
+        Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+        Instruction::AddImmediate(Register::R1, Register::R1, 3.into()),
+        Instruction::AddImmediate(Register::R7, Register::R7, 7.into()),
+        Instruction::trap_halt(),
+        Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+        Instruction::trap_halt(),
+    ]);
+
+    let out = run_given_in_out(&mut machine, &[]);
+    assert_eq!(out, HALT_MESSAGE);
+
+    // the clock is stopped, the program's registers are untouched
+    assert_eq!(machine.memory[MCR] as u16 & (1 << 15), 0);
+    assert_eq!(machine.registers.get(Register::R0), 1);
+    assert_eq!(machine.registers.get(Register::R1), 3);
+    assert_eq!(machine.registers.get(Register::R7), 7);
+
+    // like on lc3tools, the machine stops inside the trap: R6 is the supervisor stack
+    // holding the trap's PSR and PC, the program's stack pointer is banked
+    assert_eq!(machine.privilege, PrivilegeMode::Supervisor);
+    assert_eq!(machine.registers.get(Register::R6), BASIC_OS_SSP as i16 - 2);
+    assert_eq!(machine.memory[BASIC_OS_SSP - 2], 0x3004);
+    assert_eq!(machine.registers.usp(), 0xFE00u16 as i16);
+
+    // starting the clock again returns from the trap
+    machine.halted = false;
+    machine.set_memory_at_unchecked(MCR, (1 << 15) as i16);
+    run_given_in_out(&mut machine, &[]);
+    assert_eq!(machine.registers.get(Register::R0), 2);
+    assert_eq!(machine.registers.get(Register::R6), BASIC_OS_SSP as i16 - 2);
+    assert_eq!(machine.registers.usp(), 0xFE00u16 as i16);
+    assert_eq!(machine.privilege, PrivilegeMode::Supervisor);
+}
+
+#[test]
+fn replaced_halt_routine() {
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::trap_halt(),
+            Instruction::AddImmediate(Register::R1, Register::R1, 2.into()),
+            Instruction::StoreIndirect(Register::R1, 0.into()), // clear the MCR ourselves
+        ],
+    );
+    machine.set_memory_at_unchecked(0x3003, MCR as i16);
+
+    // a HALT that does nothing but return
+    machine.set_memory_at_unchecked(0x25, 0x4000);
+    machine.set_memory_at_unchecked(0x4000, Instruction::ReturnFromInterrupt.encode() as i16);
+
+    let out = run_given_in_out(&mut machine, &[]);
+    assert_eq!(out, "");
+    assert_eq!(machine.registers.get(Register::R1), 2);
+}
+
+#[test]
+fn custom_os() {
+    // an OS with nothing but a HALT that stops the clock without a message, the way lc3tools
+    // writes it: AND R0, R0, #0 / STI R0, MCR_ADDR / MCR_ADDR .fill xFFFE / SAVED_SSP .fill x2000
+    let os = read_complex::read(
+        br#"
+LC-3 OBJ FILE
+
+.TEXT
+0025
+1
+0400
+0400
+4
+5020
+B000
+FFFE
+2000
+
+.SYMBOL
+ADDR | EXT | LABEL
+0402 |   0 | MCR_ADDR
+0403 |   0 | SAVED_SSP
+"#,
+    );
+
+    let mut machine = Machine::new_without_os(0x3000, true, true, &[Instruction::trap_halt()]);
+    assert!(!machine.memory.is_touched(0x20));
+
+    machine.load_os(&os);
+    assert_eq!(machine.registers.ssp(), 0x2000);
+
+    let out = run_given_in_out(&mut machine, &[]);
+    assert_eq!(out, "");
+    assert_eq!(machine.registers.get(Register::R6), 0x2000 - 2);
+    assert_eq!(machine.memory[0x2000 - 2], 0x3001);
+    assert!(machine.symbol_address(SAVED_SSP_LABEL).is_none());
+}
+
 #[test]
 fn test_in() {
     let mut machine = Machine::new(
@@ -483,27 +673,2425 @@ fn test_in() {
 
     let res = run_given_in_out(&mut machine, &['5' as u8]);
 
-    assert_eq!(res, "Prompt:5");
+    assert_eq!(res, format!("Prompt:5{HALT_MESSAGE}"));
 }
 
-pub fn run_given_in_out(machine: &mut Machine, input: &[u8]) -> String {
-    let mut buf = String::new();
+#[test]
+fn buffer_console() {
+    let mut machine = Machine::new_x3000(&[
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_halt(),
+    ]);
+    let mut console = BufferConsole::new(b"hi!");
+
+    assert_eq!(
+        machine.run_with_console(&mut console).unwrap(),
+        ConsoleExit::Halted
+    );
+    assert_eq!(console.output(), format!("hi{HALT_MESSAGE}"));
+    // the last key waits in the keyboard
+    assert_eq!(console.remaining_input(), 0);
+    assert!(machine.get_keyboard_status());
+}
+
+#[test]
+fn pipe_console() {
+    let mut machine = Machine::new_x3000(&[
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_halt(),
+    ]);
+    let mut console = PipeConsole::new(std::io::Cursor::new(b"ok".to_vec()), Vec::new());
+
+    machine.run_with_console(&mut console).unwrap();
+    let output = String::from_utf8(console.into_output()).unwrap();
+    assert_eq!(output, format!("ok{HALT_MESSAGE}"));
+}
+
+#[test]
+fn type_ahead() {
+    let echo_twice_with_keys = |capacity, overflow, keys: &[u8]| {
+        let mut machine = Machine::new_x3000(&[
+            Instruction::trap_get_c(),
+            Instruction::trap_out(),
+            Instruction::trap_get_c(),
+            Instruction::trap_out(),
+            Instruction::trap_halt(),
+        ]);
+        assert!(machine.set_type_ahead(capacity, overflow));
+
+        let kept: Vec<bool> = keys
+            .iter()
+            .map(|&key| machine.set_keyboard_key(key as u16))
+            .collect();
+        (machine, kept)
+    };
+
+    let (mut machine, kept) =
+        echo_twice_with_keys(DEFAULT_TYPE_AHEAD, OverflowPolicy::default(), b"hi");
+    assert_eq!(kept, [true, true]);
+    assert_eq!(
+        run_given_in_out(&mut machine, &[]),
+        format!("hi{HALT_MESSAGE}")
+    );
+
+    // one key in KBDR and two waiting
+    let (mut machine, kept) = echo_twice_with_keys(2, OverflowPolicy::DropNewest, b"abcd");
+    assert_eq!(kept, [true, true, true, false]);
+    assert!(!machine.keyboard_has_room());
+    assert_eq!(
+        run_given_in_out(&mut machine, &[]),
+        format!("ab{HALT_MESSAGE}")
+    );
+    assert_eq!(machine.device::<Keyboard>().unwrap().pending(), 0);
+    assert_eq!(machine.memory[KBDR], 'c' as i16);
+
+    let (mut machine, kept) = echo_twice_with_keys(2, OverflowPolicy::DropOldest, b"abcd");
+    assert_eq!(kept, [true, true, true, true]);
+    assert_eq!(
+        run_given_in_out(&mut machine, &[]),
+        format!("ac{HALT_MESSAGE}")
+    );
+
+    let machine = Machine::builder()
+        .type_ahead(1, OverflowPolicy::DropOldest)
+        .build();
+    let keyboard = machine.device::<Keyboard>().unwrap();
+    assert_eq!(keyboard.capacity(), 1);
+    assert_eq!(keyboard.overflow(), OverflowPolicy::DropOldest);
+}
+
+#[test]
+fn preloaded_input() {
+    let mut machine = Machine::new_x3000(&[
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_halt(),
+    ]);
+    machine.set_type_ahead(1, OverflowPolicy::DropNewest);
+
+    // more than the type-ahead buffer holds, but nothing is dropped
+    assert!(machine.preload_input(b"hello"));
+    assert_eq!(machine.device::<Keyboard>().unwrap().pending(), 4);
+
+    assert_eq!(
+        run_given_in_out(&mut machine, &[]),
+        format!("he{HALT_MESSAGE}")
+    );
+    assert_eq!(machine.device::<Keyboard>().unwrap().pending(), 2);
+}
+
+#[test]
+fn keyboard_interrupt_enable() {
+    let mut machine = Machine::new_x3000(&[]);
+    assert!(machine.get_keyboard_interrupt_enable_bit());
+
+    machine.set_keyboard_interrupts(false);
+    assert!(!machine.get_keyboard_interrupt_enable_bit());
+    machine.set_keyboard_key('a' as u16);
+    assert!(machine.pending_interrupts().is_empty());
+
+    machine.set_keyboard_interrupts(true);
+    assert!(machine.get_keyboard_interrupt_enable_bit());
+}
+
+// stops after a number of instructions, without ever giving a key
+struct StoppingConsole(usize);
+
+impl Console for StoppingConsole {
+    fn read_key(&mut self) -> std::io::Result<Option<u16>> {
+        Ok(None)
+    }
+
+    fn write_char(&mut self, _data: u16) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn stop_requested(&mut self) -> std::io::Result<bool> {
+        self.0 = self.0.saturating_sub(1);
+        Ok(self.0 == 0)
+    }
+}
+
+#[test]
+fn console_stop() {
+    let mut machine = Machine::new_x3000(&[
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_halt(),
+    ]);
+    let mut console = StoppingConsole(50);
+
+    assert_eq!(
+        machine.run_with_console(&mut console).unwrap(),
+        ConsoleExit::Stopped
+    );
+    assert_eq!(machine.instruction_count, 49);
+    assert!(!machine.halted);
+}
+
+#[test]
+fn memory_unset_reads_zero() {
+    let machine = Machine::new_x3000(&[]);
+
+    assert_eq!(machine.memory[0x4000], 0);
+    assert_eq!(machine.memory[0xFDFF], 0);
+    assert!(!machine.memory.is_touched(0x4000));
+}
+
+#[test]
+fn memory_regions() {
+    let mut memory = Memory::new();
+
+    memory[0x3000] = 1;
+    memory[0x3001] = 2;
+    memory[0x3002] = 0; // written zeros still count as touched
+    memory[0x303F] = 4;
+    memory[0x3040] = 5;
+    memory[0xFFFF] = 6;
+
+    let regions: Vec<(u16, Vec<i16>)> = memory
+        .regions()
+        .map(|(start, words)| (start, words.to_vec()))
+        .collect();
+
+    assert_eq!(
+        regions,
+        vec![
+            (0x3000, vec![1, 2, 0]),
+            (0x303F, vec![4, 5]),
+            (0xFFFF, vec![6]),
+        ]
+    );
+
+    let touched: Vec<(u16, i16)> = memory.touched().collect();
+    assert_eq!(touched.len(), 6);
+    assert_eq!(touched[3], (0x303F, 4));
+}
+
+#[test]
+fn memory_init() {
+    let mut machine = Machine::new_x3000(&[]);
+    let os_word = machine.memory[0x0200];
+
+    machine.set_memory_init(MemoryInit::Pattern(0x7777));
+    assert_eq!(machine.memory[0x4000], 0x7777);
+    assert_eq!(machine.memory[0x0200], os_word);
+
+    machine.set_memory_init(MemoryInit::Random(1));
+    let first: Vec<i16> = (0x4000..0x4010).map(|i| machine.memory[i]).collect();
+    assert!(first.iter().any(|word| *word != first[0]));
+
+    // reproducible from the seed alone
+    let memory = Memory::with_init(MemoryInit::Random(1));
+    assert!(
+        (0x4000..0x4010)
+            .map(|i| memory[i])
+            .eq(first.iter().copied())
+    );
+
+    let memory = Memory::with_init(MemoryInit::Random(2));
+    assert!(
+        !(0x4000..0x4010)
+            .map(|i| memory[i])
+            .eq(first.iter().copied())
+    );
+
+    // forgetting a write brings the initial value back
+    machine.memory[0x4000] = 5;
+    machine.memory.clear(0x4000);
+    assert_eq!(machine.memory[0x4000], first[0]);
+    assert!(!machine.memory.is_touched(0x4000));
+}
+
+#[test]
+fn random_memory_init_keyboard() {
+    // the basic OS has no keyboard handler, the interrupt must not jump through x0180
+    let mut machine = Machine::new_x3000(&[
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_halt(),
+    ]);
+    machine.set_memory_init(MemoryInit::Random(1));
+
+    let out = run_given_in_out(&mut machine, b"ab");
+    assert_eq!(out, format!("ab{HALT_MESSAGE}"));
+}
+
+#[test]
+fn random_memory_init_devices() {
+    let mut machine = Machine::new_x3000(&[]);
+    machine.set_memory_init(MemoryInit::Random(1));
+    machine.attach_device(ToneGenerator::default());
+    machine.attach_device(BlockDevice::new(std::io::Cursor::new(vec![0u8; 512])).unwrap());
+    machine.attach_device(Clock::virtual_clock());
+
+    assert_eq!(machine.memory[KBSR], 1 << 14);
+    assert_eq!(machine.memory[DSR], 1 << 15);
+    assert_eq!(machine.memory[DDR], 0);
+    assert_eq!(machine.memory[SNDF], 0);
+    assert_eq!(machine.memory[SNDS], 1 << 15);
+    assert_eq!(machine.memory[DKSN], 0);
+    assert_eq!(machine.memory[DKBA], 0);
+    assert_eq!(machine.memory[CNTH], 0);
+}
+
+#[test]
+fn blkw_follows_memory_init() {
+    let program = b"LC-3 OBJ FILE\n\n.TEXT\n3000\n4\n2001\nF025\n????\n????\n";
+    let info = read_complex::read(program);
+    assert_eq!(info.data[0].uninitialized, vec![2, 3]);
+
+    let mut machine = Machine::new_x3000(&[]);
+    machine.set_memory_init(MemoryInit::Pattern(0x1234));
+    machine.load_assembly(&info);
+
+    assert_eq!(machine.memory[0x3002], 0x1234);
+    assert!(!machine.memory.is_touched(0x3003));
+
+    machine.step();
+    assert_eq!(machine.registers.get(Register::R0), 0x1234);
+}
+
+#[test]
+fn uninitialized_reads() {
+    let program = b"LC-3 OBJ FILE\n\n.TEXT\n3000\n7\n1061\n2404\n2403\n56E0\n16C0\nF025\n????\n";
+    let info = read_complex::read(program);
+
+    let mut machine = Machine::new_x3000(&[]);
+    machine.load_assembly(&info);
+    machine.enable_init_checks();
+    machine.run_until_halt();
+
+    // ADD R0, R1, #1 then LD R2, DATA twice. AND R3, R3, #0 doesn't count as a read
+    assert_eq!(
+        machine.diagnostics(),
+        &[
+            Diagnostic {
+                pc: 0x3000,
+                read: UninitializedRead::Register(Register::R1),
+            },
+            Diagnostic {
+                pc: 0x3001,
+                read: UninitializedRead::Memory(0x3006),
+            },
+        ]
+    );
+    assert_eq!(
+        machine.diagnostics()[1].to_string(),
+        "x3001: read of uninitialized memory at x3006"
+    );
+
+    assert_eq!(machine.take_diagnostics().len(), 2);
+    assert!(machine.diagnostics().is_empty());
+}
+
+#[test]
+fn host_register_writes_are_initialized() {
+    let program = b"LC-3 OBJ FILE\n\n.TEXT\n3000\n2\n1061\nF025\n";
+    let info = read_complex::read(program);
+
+    let mut machine = Machine::new_x3000(&[]);
+    machine.load_assembly(&info);
+    machine.enable_init_checks();
+    *machine.registers.get_mut(Register::R1) = 4;
+    machine.run_until_halt();
+
+    assert!(machine.diagnostics().is_empty());
+    assert_eq!(machine.registers.get(Register::R0), 5);
+}
+
+#[test]
+fn load_snapshot_resets_init_checks() {
+    // ADD R0, R1, #1 then LD R2, DATA
+    let program = b"LC-3 OBJ FILE\n\n.TEXT\n3000\n4\n1061\n2401\nF025\n????\n";
+    let info = read_complex::read(program);
+
+    let mut untouched = Machine::new_x3000(&[]);
+    untouched.load_assembly(&info);
+    let mut before_run = Vec::new();
+    untouched.save_snapshot(&mut before_run).unwrap();
+
+    untouched.set_memory_at(0x3003, 7).unwrap();
+    let mut data_written = Vec::new();
+    untouched.save_snapshot(&mut data_written).unwrap();
+
+    let mut machine = Machine::new_x3000(&[]);
+    machine.enable_init_checks();
+    machine.load_snapshot(before_run.as_slice()).unwrap();
+    machine.run_until_halt();
+    assert_eq!(machine.take_diagnostics().len(), 1);
+
+    // reported before, but the word is still untouched in the restored state
+    machine.load_snapshot(before_run.as_slice()).unwrap();
+    machine.run_until_halt();
+    assert_eq!(
+        machine.diagnostics(),
+        &[Diagnostic {
+            pc: 0x3001,
+            read: UninitializedRead::Memory(0x3003),
+        }]
+    );
+    machine.take_diagnostics();
+
+    // restored registers and touched words count as written
+    machine.load_snapshot(data_written.as_slice()).unwrap();
+    machine.run_until_halt();
+    assert!(machine.diagnostics().is_empty());
+    assert_eq!(machine.registers.get(Register::R2), 7);
+}
+
+// counts up by `step` on every read of xFE40, `step` is set by writing to xFE41
+#[derive(Default)]
+struct Counter {
+    count: i16,
+    step: i16,
+    ticks: usize,
+}
+
+impl Device for Counter {
+    fn name(&self) -> &'static str {
+        "counter"
+    }
 
-    let mut input_pointer = 0;
+    fn addresses(&self) -> RangeInclusive<u16> {
+        0xFE40..=0xFE41
+    }
 
-    while !machine.halted {
-        // update input
-        if input_pointer < input.len() && machine.set_keyboard_key(input[input_pointer] as u16) {
-            input_pointer += 1;
+    fn read(&mut self, _machine: &mut Machine, address: u16, value: i16) -> i16 {
+        if address == 0xFE40 {
+            self.count += self.step;
+            self.count
+        } else {
+            value
         }
+    }
 
-        // read output
-        if let Some(data) = machine.poll_display_data() {
-            // data has been set if status is NOT ready
-            let chara = (data as u8) as char;
-            buf += chara.to_string().as_str();
+    fn write(&mut self, _machine: &mut Machine, address: u16, value: i16) {
+        if address == 0xFE41 {
+            self.step = value;
         }
+    }
+
+    fn tick(&mut self, _machine: &mut Machine) {
+        self.ticks += 1;
+    }
+}
+
+#[test]
+fn stateful_device() {
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::AddImmediate(Register::R0, Register::R0, 3.into()),
+            Instruction::StoreIndirect(Register::R0, 4.into()), // step = 3
+            Instruction::LoadIndirect(Register::R1, 2.into()),  // r1 = 3
+            Instruction::LoadIndirect(Register::R2, 1.into()),  // r2 = 6
+            Instruction::trap_halt(),
+        ],
+    );
+
+    machine.set_span_at(0x3005, &[0xFE40u16 as i16, 0xFE41u16 as i16]);
+    machine.attach_device(Counter::default());
+
+    for _ in 0..4 {
+        machine.step();
+    }
+
+    assert_eq!(machine.registers.get(Register::R1), 3);
+    assert_eq!(machine.registers.get(Register::R2), 6);
+
+    let counter = machine.device::<Counter>().unwrap();
+    assert_eq!(counter.count, 6);
+    assert_eq!(counter.ticks, 4);
+}
+
+#[test]
+fn devices_only_see_their_addresses() {
+    let mut machine = Machine::new_x3000(&[]);
+    machine.attach_device(Counter::default());
+
+    machine.set_memory_at(0x4000, 7).unwrap();
+    machine.get_memory_at(0x4000).unwrap();
+
+    assert_eq!(machine.device::<Counter>().unwrap().count, 0);
+    assert!(machine.is_address_mapped_to_device(0xFE41));
+    assert!(!machine.is_address_mapped_to_device(0xFE42));
+}
+
+// only wants ticks for the next `armed` instructions, arms itself on a write
+#[derive(Default)]
+struct OneShot {
+    armed: u16,
+    ticks: usize,
+}
+
+impl Device for OneShot {
+    fn name(&self) -> &'static str {
+        "one_shot"
+    }
+
+    fn addresses(&self) -> RangeInclusive<u16> {
+        0xFE40..=0xFE40
+    }
+
+    fn write(&mut self, _machine: &mut Machine, _address: u16, value: i16) {
+        self.armed = value as u16;
+    }
+
+    fn ticks(&self) -> bool {
+        self.armed > 0
+    }
+
+    fn tick(&mut self, _machine: &mut Machine) {
+        self.armed -= 1;
+        self.ticks += 1;
+    }
+}
+
+#[test]
+fn idle_devices_are_not_ticked() {
+    let mut machine = Machine::new(0x3000, false, false, &[]);
+    machine.attach_device(OneShot::default());
+
+    for _ in 0..3 {
+        machine.step();
+    }
+    assert_eq!(machine.device::<OneShot>().unwrap().ticks, 0);
+
+    machine.set_memory_at(0xFE40, 2).unwrap();
+    for _ in 0..3 {
         machine.step();
     }
-    buf
+    assert_eq!(machine.device::<OneShot>().unwrap().ticks, 2);
+
+    // handing the device out wakes it up
+    machine.device_mut::<OneShot>().unwrap().armed = 1;
+    machine.step();
+    assert_eq!(machine.device::<OneShot>().unwrap().ticks, 3);
+}
+
+#[test]
+#[allow(deprecated)]
+fn io_callbacks_still_work() {
+    use crate::vm::machine::MemoryModificationEvent;
+
+    let mut machine = Machine::new(0x3000, false, false, &[]);
+    machine.add_io_callback(0xFE40, |machine, _| {
+        *machine.registers.get_mut(Register::R0) += 1
+    });
+    machine.add_io_callback(0xFE40, |machine, event| {
+        if let MemoryModificationEvent::Write(value) = event {
+            *machine.registers.get_mut(Register::R1) = value;
+        }
+    });
+
+    machine.set_memory_at(0xFE40, 9).unwrap();
+    machine.get_memory_at(0xFE40).unwrap();
+
+    // the second callback replaced the first
+    assert_eq!(machine.registers.get(Register::R0), 0);
+    assert_eq!(machine.registers.get(Register::R1), 9);
+}
+
+#[test]
+fn interrupts_wait_for_priority() {
+    // spins at x3000, ISR A (xA0) counts in R1 and ISR B (xA1) in R2
+    let mut machine = machine_with_devices(
+        vec![],
+        true,
+        &[Instruction::Branch(0b111.into(), (-1).into())],
+        &[],
+    );
+
+    for (vector, address, register) in [(0xA0, 0x4000, Register::R1), (0xA1, 0x4100, Register::R2)]
+    {
+        load_handler(
+            &mut machine,
+            vector,
+            address,
+            &[
+                Instruction::AddImmediate(register, register, 1.into()),
+                Instruction::ReturnFromInterrupt,
+            ],
+            &[],
+        );
+    }
+
+    machine.interrupt(0xA0, 6);
+    machine.step();
+    assert_eq!(machine.registers.get(Register::R1), 1);
+    assert_eq!(machine.priority, 6);
+
+    // lower priority, has to wait for the RTI
+    machine.interrupt(0xA1, 2);
+    machine.step();
+    assert_eq!(machine.ip, 0x3000);
+    assert_eq!(machine.priority, 0);
+    assert_eq!(
+        machine.pending_interrupts(),
+        &[InterruptRequest {
+            vector: 0xA1,
+            priority: 2
+        }]
+    );
+
+    machine.step();
+    assert_eq!(machine.registers.get(Register::R2), 1);
+    assert_eq!(machine.priority, 2);
+    assert!(machine.pending_interrupts().is_empty());
+
+    // equal priority doesn't preempt
+    machine.interrupt(0xA0, 2);
+    machine.step();
+    assert_eq!(machine.ip, 0x3000);
+    machine.step();
+    assert_eq!(machine.registers.get(Register::R1), 2);
+}
+
+#[test]
+fn interrupt_arbitration() {
+    // spins at x3000, ISR A (xA0) counts in R1 and ISR B (xA1) in R2
+    let mut machine = machine_with_devices(
+        vec![],
+        true,
+        &[Instruction::Branch(0b111.into(), (-1).into())],
+        &[],
+    );
+
+    for (vector, address, register) in [(0xA0, 0x4000, Register::R1), (0xA1, 0x4100, Register::R2)]
+    {
+        load_handler(
+            &mut machine,
+            vector,
+            address,
+            &[
+                Instruction::AddImmediate(register, register, 1.into()),
+                Instruction::ReturnFromInterrupt,
+            ],
+            &[],
+        );
+    }
+
+    // raised together, the more urgent goes first
+    machine.interrupt(0xA1, 2);
+    machine.interrupt(0xA0, 6);
+    machine.step();
+    assert_eq!(machine.registers.get(Register::R1), 1);
+    assert_eq!(machine.registers.get(Register::R2), 0);
+
+    machine.step(); // RTI
+    machine.step();
+    assert_eq!(machine.registers.get(Register::R2), 1);
+
+    // a more urgent one preempts a running handler
+    machine.step(); // RTI
+    machine.interrupt(0xA1, 2);
+    machine.step();
+    machine.interrupt(0xA0, 6);
+    machine.step();
+    assert_eq!(machine.registers.get(Register::R1), 2);
+    assert_eq!(machine.ip, 0x4001);
+
+    machine.interrupt(0xA1, 1);
+    assert!(machine.cancel_interrupt(0xA1));
+    assert!(!machine.cancel_interrupt(0xA1));
+}
+
+#[test]
+fn timer_interrupts() {
+    // enables the timer and spins until the ISR has counted to 5
+    let mut machine = machine_with_devices(
+        vec![Box::new(Timer::default())],
+        false,
+        &[
+            Instruction::Load(Register::R1, 7.into()),
+            Instruction::StoreIndirect(Register::R1, 7.into()), // TMI = 20
+            Instruction::Load(Register::R1, 7.into()),
+            Instruction::StoreIndirect(Register::R1, 7.into()), // TMR = interrupt enable
+            Instruction::LoadIndirect(Register::R2, 7.into()),  // r2 = count
+            Instruction::AddImmediate(Register::R2, Register::R2, (-5).into()),
+            Instruction::Branch(0b100.into(), (-3).into()),
+            Instruction::trap_halt(),
+        ],
+        &[
+            20, // interval
+            TMI as i16,
+            1 << 14,
+            TMR as i16,
+            0x4100, // count address
+        ],
+    );
+
+    // ISR, increments the count
+    load_handler(
+        &mut machine,
+        TIMER_INTERRUPT_VECTOR,
+        0x4000,
+        &[
+            Instruction::Store(Register::R0, 5.into()),
+            Instruction::LoadIndirect(Register::R0, 5.into()),
+            Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+            Instruction::StoreIndirect(Register::R0, 3.into()),
+            Instruction::Load(Register::R0, 1.into()),
+            Instruction::ReturnFromInterrupt,
+        ],
+        &[
+            0,      // saved R0
+            0x4100, // count address
+        ],
+    );
+
+    // run up to the HALT
+    let mut steps = 0;
+    while machine.ip != 0x3007 {
+        machine.step();
+        steps += 1;
+    }
+
+    assert_eq!(machine.memory[0x4100], 5);
+    assert!(steps > 100 && steps < 150, "{steps}");
+
+    // interrupt handling restored user mode and the original R0
+    assert_eq!(machine.privilege, PrivilegeMode::User);
+    assert_eq!(machine.registers.get(Register::R0), 0);
+
+    // the timer keeps interrupting the HALT routine, which must not disturb the program's state
+    machine.run_until_halt();
+    assert!(machine.memory[0x4100] > 5);
+    assert_eq!(machine.registers.get(Register::R0), 0);
+    assert_eq!(machine.registers.usp(), 0xFE00u16 as i16);
+}
+
+#[test]
+fn timer_attach_clears_registers() {
+    let mut machine = Machine::new_x3000(&[Instruction::Branch(0b111.into(), (-1).into())]);
+    machine.set_memory_init(MemoryInit::Random(1));
+    machine.attach_device(Timer::default());
+
+    assert_eq!(machine.memory[TMR], 0);
+    assert_eq!(machine.memory[TMI], 0);
+
+    machine.step();
+    assert!(machine.pending_interrupts().is_empty());
+}
+
+#[test]
+fn timer_status_without_interrupts() {
+    let mut machine = machine_with_devices(
+        vec![Box::new(Timer::default())],
+        false,
+        &[Instruction::Branch(0b111.into(), (-1).into())],
+        &[],
+    );
+    machine.set_memory_at(TMI, 3).unwrap();
+
+    machine.step();
+    machine.step();
+    assert_eq!(machine.memory[TMR], 0);
+
+    machine.step();
+    assert!(machine.memory[TMR] < 0); // 15th bit set
+    assert_eq!(machine.device::<Timer>().unwrap().remaining(), 3);
+    assert_eq!(machine.ip, 0x3000); // no interrupt taken
+
+    // reading TMR acknowledges the timer
+    assert!(machine.get_memory_at(TMR).unwrap() < 0);
+    assert_eq!(machine.memory[TMR], 0);
+
+    // interval of zero stops the timer
+    machine.set_memory_at(TMI, 0).unwrap();
+    for _ in 0..10 {
+        machine.step();
+    }
+    assert_eq!(machine.memory[TMR], 0);
+}
+
+#[test]
+fn interrupt_driven_output() {
+    use Instruction::*;
+    use Register::*;
+
+    // queues a string, the display ISR writes it out one character per ready interrupt
+    let mut machine = machine_with_devices(
+        vec![],
+        false,
+        &[
+            Load(R1, 5.into()),
+            StoreIndirect(R1, 5.into()), // enable display interrupts
+            LoadIndirect(R2, 5.into()),  // wait for the queue to drain
+            LoadRegister(R2, R2, 0.into()),
+            Branch(0b101.into(), (-3).into()),
+            Instruction::trap_halt(),
+        ],
+        &[0x4000, DSR as i16, 0x4100],
+    );
+
+    load_handler(
+        &mut machine,
+        DISPLAY_INTERRUPT_VECTOR,
+        0x4000,
+        &[
+            Store(R0, 13.into()),
+            Store(R1, 13.into()),
+            LoadIndirect(R1, 13.into()),
+            LoadRegister(R0, R1, 0.into()),
+            Branch(0b010.into(), 4.into()),
+            StoreIndirect(R0, 11.into()), // next character
+            AddImmediate(R1, R1, 1.into()),
+            StoreIndirect(R1, 8.into()),
+            Branch(0b111.into(), 2.into()),
+            AndImmediate(R0, R0, 0.into()),
+            StoreIndirect(R0, 7.into()), // queue is empty, stop the interrupts
+            Load(R0, 2.into()),
+            Load(R1, 2.into()),
+            ReturnFromInterrupt,
+        ],
+        &[0, 0, 0x4100, DDR as i16, DSR as i16],
+    );
+
+    machine.set_memory_at_unchecked(0x4100, 0x4200);
+    machine.string_set(0x4200, "Hi!\n\0");
+
+    let out = run_given_in_out(&mut machine, &[]);
+    assert_eq!(out, format!("Hi!\n{HALT_MESSAGE}"));
+    assert!(!machine.get_display_interrupt_enable_bit());
+    assert_eq!(machine.get_memory_at(0x4100).unwrap(), 0x4204);
+}
+
+#[test]
+fn display_output_is_bounded() {
+    use Instruction::*;
+    use Register::*;
+
+    // prints 'a' forever
+    let mut machine = Machine::new_x3000(&[
+        Load(R0, 2.into()),
+        Instruction::trap_out(),
+        Branch(0b111.into(), (-2).into()),
+    ]);
+    machine.set_memory_at_unchecked(0x3003, 'a' as i16);
+
+    for _ in 0..100_000 {
+        machine.step();
+    }
+
+    // nobody collected the output, so the display is stuck busy
+    let display = machine.device::<Display>().unwrap();
+    assert_eq!(display.pending_output(), OUTPUT_CAPACITY);
+    assert!(!machine.get_display_status());
+
+    assert_eq!(machine.poll_display_data(), Some('a' as u16));
+    machine.step();
+    assert!(machine.get_display_status());
+}
+
+#[test]
+fn display_interrupt_needs_enable_bit() {
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[Instruction::Branch(0b111.into(), (-1).into())],
+    );
+    assert!(machine.get_display_status());
+
+    // writing DSR can't clear the ready bit
+    machine.set_memory_at(DSR, 0).unwrap();
+    assert!(machine.get_display_status());
+
+    machine.set_memory_at(DDR, 'a' as i16).unwrap();
+    machine.step();
+    assert!(machine.get_display_status());
+    assert!(machine.pending_interrupts().is_empty());
+
+    // enabling while ready asks right away
+    machine.set_memory_at(DSR, 1 << 14).unwrap();
+    assert_eq!(
+        machine.pending_interrupts(),
+        &[InterruptRequest {
+            vector: DISPLAY_INTERRUPT_VECTOR,
+            priority: DISPLAY_INTERRUPT_PRIORITY
+        }]
+    );
+}
+
+#[test]
+fn masked_requests_follow_the_interrupt_line() {
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[Instruction::Branch(0b111.into(), (-1).into())],
+    );
+    machine.priority = 7;
+
+    // clearing IE while the keyboard request is masked withdraws it
+    machine.set_keyboard_key('a' as u16);
+    assert_eq!(machine.pending_interrupts().len(), 1);
+    machine.set_memory_at(KBSR, (1 << 15) as i16).unwrap();
+    assert!(machine.pending_interrupts().is_empty());
+
+    // and so does reading the key
+    machine.set_keyboard_interrupts(true);
+    assert_eq!(machine.pending_interrupts().len(), 1);
+    assert_eq!(machine.get_memory_at(KBDR).unwrap(), 'a' as i16);
+    assert!(machine.pending_interrupts().is_empty());
+
+    machine.set_memory_at(DSR, 1 << 14).unwrap();
+    assert_eq!(machine.pending_interrupts().len(), 1);
+    machine.set_memory_at(DSR, 0).unwrap();
+    assert!(machine.pending_interrupts().is_empty());
+
+    // nothing is taken once the priority drops
+    machine.priority = 0;
+    machine.step();
+    assert_eq!(machine.ip, 0x3000);
+    assert_eq!(machine.privilege, PrivilegeMode::User);
+}
+
+#[test]
+fn video_frame() {
+    use Instruction::*;
+    use Register::*;
+
+    // a red pixel at (1, 0)
+    let mut machine = machine_with_devices(
+        vec![],
+        true,
+        &[
+            Load(R0, 2.into()),
+            StoreIndirect(R0, 2.into()),
+            Instruction::trap_halt(),
+        ],
+        &[0x7C00, (VIDEO_START + 1) as i16],
+    );
+    assert!(machine.frame().is_none());
+
+    machine.attach_device(Video);
+    machine.memory[VIDEO_END] = 0b00000_00000_10000; // half blue, bottom right
+    machine.run_until_halt();
+
+    let frame = machine.frame().unwrap();
+    assert_eq!((frame.width(), frame.height()), (128, 124));
+    assert_eq!(frame.pixel(1, 0), 0x7C00);
+    assert_eq!(frame.rgb(1, 0), [255, 0, 0]);
+    assert_eq!(frame.rgb(127, 123), [0, 0, 132]);
+    assert_eq!(frame.rgb(0, 0), [0, 0, 0]);
+
+    let mut ppm = Vec::new();
+    frame.write_ppm(&mut ppm).unwrap();
+    let header = b"P6\n128 124\n255\n";
+    assert!(ppm.starts_with(header));
+    assert_eq!(ppm.len(), header.len() + 128 * 124 * 3);
+    assert_eq!(ppm[header.len() + 3..header.len() + 6], [255, 0, 0]);
+}
+
+#[test]
+fn video_memory_is_plain_memory() {
+    let mut machine = Machine::new_x3000(&[]);
+    machine.set_memory_init(MemoryInit::Random(7));
+    machine.attach_device(Video);
+
+    assert!(!machine.is_address_mapped_to_device(VIDEO_START));
+    assert!(!(VIDEO_START..=VIDEO_END).any(|address| machine.memory.is_touched(address)));
+
+    // untouched pixels show black, whatever the memory holds
+    assert_ne!(machine.memory[VIDEO_START], 0);
+    assert_eq!(machine.frame().unwrap().pixel(0, 0), 0);
+}
+
+#[test]
+fn video_png() {
+    use Instruction::*;
+    use Register::*;
+
+    // a red pixel at (1, 0)
+    let mut machine = machine_with_devices(
+        vec![],
+        true,
+        &[
+            Load(R0, 2.into()),
+            StoreIndirect(R0, 2.into()),
+            Instruction::trap_halt(),
+        ],
+        &[0x7C00, (VIDEO_START + 1) as i16],
+    );
+    machine.attach_device(Video);
+    machine.run_until_halt();
+
+    let mut png = Vec::new();
+    machine.frame().unwrap().write_png(&mut png).unwrap();
+
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x80\0\0\0\x7c\x08\x02"));
+    assert!(png.ends_with(b"\0\0\0\0IEND\xae\x42\x60\x82"));
+
+    // the pixel data is stored uncompressed, the first row after its filter byte
+    let data = png.windows(4).position(|kind| kind == b"IDAT").unwrap() + 4;
+    let first_block = &png[data + 2..];
+    let row = &first_block[5 + 1..5 + 1 + 128 * 3];
+    assert_eq!(row[3..6], [255, 0, 0]);
+}
+
+fn sign_changes(samples: &[i16]) -> usize {
+    samples
+        .windows(2)
+        .filter(|pair| pair[0].signum() != pair[1].signum())
+        .count()
+}
+
+#[test]
+fn tone_generator() {
+    use Instruction::*;
+    use Register::*;
+
+    // plays 441 Hz then 882 Hz, both for 1000 instructions
+    let mut machine = machine_with_devices(
+        vec![Box::new(ToneGenerator::default())],
+        false,
+        &[
+            Load(R0, 12.into()),
+            StoreIndirect(R0, 14.into()),
+            Load(R0, 12.into()),
+            StoreIndirect(R0, 13.into()),
+            LoadIndirect(R1, 13.into()), // wait for the note to end
+            Branch(0b011.into(), (-2).into()),
+            Load(R0, 7.into()),
+            StoreIndirect(R0, 8.into()),
+            Load(R0, 6.into()),
+            StoreIndirect(R0, 7.into()),
+            LoadIndirect(R1, 7.into()),
+            Branch(0b011.into(), (-2).into()),
+            Instruction::trap_halt(),
+        ],
+        &[441, 882, 1000, SNDF as i16, SNDD as i16, SNDS as i16],
+    );
+    machine.run_until_halt();
+
+    let generator = machine.device::<ToneGenerator>().unwrap();
+    let tones = generator.tones();
+    assert_eq!(tones.len(), 2);
+    assert_eq!(
+        tones[0],
+        Tone {
+            start: 4,
+            duration: 1000,
+            frequency: 441
+        }
+    );
+    assert_eq!((tones[1].duration, tones[1].frequency), (1000, 882));
+    assert!(tones[1].start > tones[0].start + tones[0].duration);
+
+    // 1000 instructions at the default clock are 4410 samples
+    let samples = generator.samples();
+    let second = tones[1].start as usize * 441 / 100;
+    assert_eq!(samples.len(), second + 4410);
+    assert!(samples[..17].iter().all(|&sample| sample == 0));
+    assert_eq!(sign_changes(&samples[17..17 + 4410]), 88);
+    assert_eq!(sign_changes(&samples[second..]), 176);
+
+    let mut wav = Vec::new();
+    generator.write_wav(&mut wav).unwrap();
+    assert!(wav.starts_with(b"RIFF"));
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(wav.len(), 44 + samples.len() * 2);
+    assert_eq!(wav[44..46], samples[0].to_le_bytes());
+    assert!(
+        wav[44..]
+            .chunks(2)
+            .zip(&samples)
+            .all(|(bytes, sample)| bytes == sample.to_le_bytes())
+    );
+}
+
+#[test]
+fn tone_generator_wav_too_long() {
+    // a single note at one instruction per second lasts over 18 hours
+    let mut machine = machine_with_devices(
+        vec![Box::new(ToneGenerator::new(1))],
+        false,
+        &[Instruction::Branch(0b111.into(), (-1).into())],
+        &[],
+    );
+    machine.set_memory_at(SNDF, 440).unwrap();
+    machine.set_memory_at(SNDD, -1).unwrap();
+
+    let generator = machine.device::<ToneGenerator>().unwrap();
+    assert_eq!(generator.sample_count(), 0xFFFF * SAMPLE_RATE as u64);
+
+    let mut wav = Vec::new();
+    let error = generator.write_wav(&mut wav).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(wav.is_empty());
+}
+
+#[test]
+fn tone_generator_user_accessible() {
+    let mut machine = Machine::new_x3000(&[]);
+    assert!(machine.set_memory_at(SNDF, 440).is_err());
+
+    machine.attach_device(ToneGenerator::default());
+    assert!(machine.set_memory_at(SNDF, 440).is_ok());
+    assert!(machine.set_memory_at(SNDD, 100).is_ok());
+}
+
+#[test]
+fn mpr_protects_each_block() {
+    for block in 0..16u16 {
+        let address = (block << 12) | 0x0123;
+        let mut machine = Machine::builder()
+            .protect_system_memory(false)
+            .protect_device_memory(false)
+            .mpr(!(1 << block))
+            .build();
+
+        assert_eq!(
+            machine.set_memory_at(address, 1),
+            Err(Lc3Error::IllegalMemoryAccess(address))
+        );
+        assert_eq!(
+            machine.get_memory_at(address),
+            Err(Lc3Error::IllegalMemoryAccess(address))
+        );
+
+        // only that block is closed
+        let next = address.wrapping_add(0x1000);
+        assert!(machine.set_memory_at(next, 1).is_ok());
+
+        // and it stays closed
+        assert!(machine.set_memory_at(MPR, -1).is_err());
+        assert!(machine.set_memory_at(address, 1).is_err());
+
+        machine.set_privilege(PrivilegeMode::Supervisor);
+        assert!(machine.set_memory_at(address, 1).is_ok());
+    }
+}
+
+#[test]
+fn mpr_access_violation() {
+    use Instruction::*;
+    use Register::*;
+
+    for block in 0..16u16 {
+        let mut machine = Machine::builder()
+            .instructions(&[LoadIndirect(R0, 1.into()), Instruction::trap_halt()])
+            .protect_system_memory(false)
+            .mpr(!(1 << block))
+            .build();
+        machine.set_memory_at_unchecked(0x3002, ((block << 12) | 0x0F00) as i16);
+
+        let out = run_given_in_out(&mut machine, &[]);
+        assert_eq!(out, format!("[exc] ACV\n{HALT_MESSAGE}"), "block {block}");
+    }
+}
+
+#[test]
+fn mpr_register() {
+    let mut machine = Machine::new_x3000(&[]);
+    assert_eq!(machine.get_memory_at_unchecked(MPR), -1);
+    assert!(machine.set_memory_at(0x4000, 1).is_ok());
+
+    // user programs can't open blocks themselves
+    assert!(machine.set_memory_at(MPR, -1).is_err());
+
+    machine.set_privilege(PrivilegeMode::Supervisor);
+    machine.set_memory_at(MPR, !(1 << 4)).unwrap();
+    machine.set_privilege(PrivilegeMode::User);
+    assert!(machine.set_memory_at(0x4000, 1).is_err());
+    assert!(machine.set_memory_at(0x5000, 1).is_ok());
+}
+
+#[test]
+fn protected_ranges() {
+    let mut machine = Machine::builder()
+        .protect_range(0x4000..=0x40FF)
+        .protect_range(SNDF..=SNDS)
+        .build();
+    machine.attach_device(ToneGenerator::default());
+
+    assert!(machine.set_memory_at(0x3FFF, 1).is_ok());
+    assert!(machine.get_memory_at(0x4000).is_err());
+    assert!(machine.set_memory_at(0x40FF, 1).is_err());
+    assert!(machine.set_memory_at(0x4100, 1).is_ok());
+
+    // user accessible devices win over the ranges
+    assert!(machine.set_memory_at(SNDF, 440).is_ok());
+
+    // the defaults still apply
+    assert!(machine.set_memory_at(0x2FFF, 1).is_err());
+    assert!(machine.set_memory_at(TMI, 1).is_err());
+}
+
+// reads RNDR into R2 and R3
+#[test]
+fn rng() {
+    // two reads of RNDR, into R2 and R3
+    let program = [
+        Instruction::LoadIndirect(Register::R2, 2.into()),
+        Instruction::LoadIndirect(Register::R3, 1.into()),
+        Instruction::trap_halt(),
+    ];
+    let rng_machine = |seed| {
+        machine_with_devices(
+            vec![Box::new(Rng::new(seed))],
+            true,
+            &program,
+            &[RNDR as i16],
+        )
+    };
+
+    let random_pair = |machine: &Machine| {
+        (
+            machine.registers.get(Register::R2),
+            machine.registers.get(Register::R3),
+        )
+    };
+
+    let mut machine = rng_machine(7);
+    machine.run_until_halt();
+    let first = random_pair(&machine);
+    assert_ne!(first.0, first.1);
+
+    // same seed, same numbers
+    let mut again = rng_machine(7);
+    again.run_until_halt();
+    assert_eq!(random_pair(&again), first);
+
+    let mut other = rng_machine(8);
+    other.run_until_halt();
+    assert_ne!(random_pair(&other), first);
+
+    // reseeding restarts the sequence
+    assert!(other.seed_rng(7));
+    assert_eq!(other.get_memory_at(RNDR).unwrap(), first.0);
+    assert_eq!(other.get_memory_at(RNDR).unwrap(), first.1);
+
+    assert!(!Machine::new_x3000(&[]).seed_rng(7));
+}
+
+#[test]
+fn devices_attached_after_stepping_back() {
+    let mut machine = Machine::new_x3000(&[Instruction::Branch(0b111.into(), (-1).into())]);
+    machine.enable_history(100);
+    for _ in 0..100 {
+        machine.step();
+    }
+
+    machine.attach_device(Clock::virtual_clock());
+    machine.attach_device(ToneGenerator::default());
+    machine.step_back(50);
+
+    assert_eq!(machine.get_memory_at(RTCS), Ok(0));
+    machine.set_memory_at(SNDD, 10).unwrap();
+    assert_eq!(
+        machine.device::<ToneGenerator>().unwrap().tones()[0].start,
+        0
+    );
+}
+
+#[test]
+fn tones_after_stepping_back() {
+    let mut machine = machine_with_devices(
+        vec![Box::new(ToneGenerator::default())],
+        true,
+        &[Instruction::Branch(0b111.into(), (-1).into())],
+        &[],
+    );
+    machine.enable_history(1000);
+
+    machine.set_memory_at(SNDF, 441).unwrap();
+    machine.set_memory_at(SNDD, 1000).unwrap();
+    for _ in 0..1000 {
+        machine.step();
+    }
+
+    // the long note was never played, the short one takes its place
+    machine.step_back(1000);
+    machine.set_memory_at(SNDD, 10).unwrap();
+
+    let generator = machine.device::<ToneGenerator>().unwrap();
+    assert_eq!(
+        generator.tones(),
+        &[Tone {
+            start: 0,
+            duration: 10,
+            frequency: 441
+        }]
+    );
+    assert_eq!(generator.samples().len(), 44);
+}
+
+#[test]
+fn virtual_clock() {
+    use Instruction::*;
+    use Register::*;
+
+    let mut machine = machine_with_devices(
+        vec![Box::new(Clock::virtual_clock())],
+        true,
+        &[
+            LoadIndirect(R1, 4.into()),
+            LoadIndirect(R2, 4.into()),
+            LoadIndirect(R3, 4.into()),
+            LoadIndirect(R4, 4.into()),
+            Instruction::trap_halt(),
+        ],
+        &[CNTL as i16, CNTH as i16, RTCS as i16, RTCM as i16],
+    );
+
+    // the count is about to carry into the high word
+    machine.instruction_count = 0x1_FFFF;
+    machine.run_until_halt();
+
+    assert_eq!(machine.registers.get(R1), 0);
+    assert_eq!(machine.registers.get(R2), 2);
+    // 131074 instructions at 10000 per second
+    assert_eq!(machine.registers.get(R3), 13);
+    assert_eq!(machine.registers.get(R4), 107);
+}
+
+#[test]
+fn clock_registers_latch() {
+    let mut machine = machine_with_devices(vec![Box::new(Clock::virtual_clock())], true, &[], &[]);
+
+    machine.instruction_count = 0x3_0005;
+    assert_eq!(machine.get_memory_at(CNTL).unwrap(), 5);
+
+    // the high word stays with the low word that was read
+    machine.instruction_count = 0x7_0000;
+    assert_eq!(machine.get_memory_at(CNTH).unwrap(), 3);
+
+    // read only
+    machine.set_memory_at(CNTH, 100).unwrap();
+    assert_eq!(machine.get_memory_at(CNTH).unwrap(), 3);
+    assert_eq!(machine.get_memory_at(CNTL).unwrap(), 0);
+    assert_eq!(machine.get_memory_at(CNTH).unwrap(), 7);
+}
+
+type Disk = BlockDevice<std::io::Cursor<Vec<u8>>>;
+
+#[test]
+fn disk_read() {
+    // sector 2 counts up from 0
+    let mut image = vec![0; 4 * SECTOR_WORDS * 2];
+    for i in 0..SECTOR_WORDS {
+        let offset = (2 * SECTOR_WORDS + i) * 2;
+        image[offset..offset + 2].copy_from_slice(&(i as u16).to_be_bytes());
+    }
+
+    use Instruction::*;
+    use Register::*;
+
+    // reads sector 2 into x4000 from a protected user program, the status ends up in R2
+    let mut machine = machine_with_devices(
+        vec![Box::new(Disk::new(std::io::Cursor::new(image)).unwrap())],
+        true,
+        &[
+            Load(R0, 9.into()),
+            StoreIndirect(R0, 10.into()), // DKSN
+            Load(R0, 8.into()),
+            StoreIndirect(R0, 9.into()), // DKBA
+            AndImmediate(R0, R0, 0.into()),
+            AddImmediate(R0, R0, (DISK_READ as i16).into()),
+            StoreIndirect(R0, 7.into()), // DKCR = command
+            LoadIndirect(R2, 7.into()),  // wait until the transfer is done
+            Branch(0b011.into(), (-2).into()),
+            Instruction::trap_halt(),
+        ],
+        &[
+            2,      // sector
+            0x4000, // buffer
+            DKSN as i16,
+            DKBA as i16,
+            DKCR as i16,
+            DKSR as i16,
+        ],
+    );
+    assert_eq!(machine.device::<Disk>().unwrap().sectors(), 4);
+
+    machine.run_until_halt();
+    assert_eq!(machine.registers.get(Register::R2), i16::MIN); // ready, no error
+    for i in 0..SECTOR_WORDS as u16 {
+        assert_eq!(machine.memory[0x4000 + i], i as i16);
+    }
+}
+
+#[test]
+fn disk_write() {
+    use Instruction::*;
+    use Register::*;
+
+    // writes x4000 to sector 1 from a protected user program, the status ends up in R2
+    let mut machine = machine_with_devices(
+        vec![Box::new(
+            Disk::new(std::io::Cursor::new(vec![0; 2 * SECTOR_WORDS * 2])).unwrap(),
+        )],
+        true,
+        &[
+            Load(R0, 9.into()),
+            StoreIndirect(R0, 10.into()), // DKSN
+            Load(R0, 8.into()),
+            StoreIndirect(R0, 9.into()), // DKBA
+            AndImmediate(R0, R0, 0.into()),
+            AddImmediate(R0, R0, (DISK_WRITE as i16).into()),
+            StoreIndirect(R0, 7.into()), // DKCR = command
+            LoadIndirect(R2, 7.into()),  // wait until the transfer is done
+            Branch(0b011.into(), (-2).into()),
+            Instruction::trap_halt(),
+        ],
+        &[
+            1,      // sector
+            0x4000, // buffer
+            DKSN as i16,
+            DKBA as i16,
+            DKCR as i16,
+            DKSR as i16,
+        ],
+    );
+    let words: Vec<i16> = (0..SECTOR_WORDS as i16).map(|i| i * -3).collect();
+    machine.set_span_at(0x4000, &words);
+
+    machine.run_until_halt();
+    assert_eq!(machine.registers.get(Register::R2), i16::MIN);
+
+    let image = machine.device::<Disk>().unwrap().storage().get_ref();
+    assert!(image[..SECTOR_WORDS * 2].iter().all(|&byte| byte == 0));
+    for (i, word) in words.iter().enumerate() {
+        let offset = (SECTOR_WORDS + i) * 2;
+        assert_eq!(image[offset..offset + 2], word.to_be_bytes());
+    }
+}
+
+#[test]
+fn disk_errors() {
+    use Instruction::*;
+    use Register::*;
+
+    // reads sector 1, past the end of the image, the status ends up in R2
+    let mut machine = machine_with_devices(
+        vec![Box::new(
+            Disk::new(std::io::Cursor::new(vec![0; SECTOR_WORDS * 2])).unwrap(),
+        )],
+        true,
+        &[
+            Load(R0, 9.into()),
+            StoreIndirect(R0, 10.into()), // DKSN
+            Load(R0, 8.into()),
+            StoreIndirect(R0, 9.into()), // DKBA
+            AndImmediate(R0, R0, 0.into()),
+            AddImmediate(R0, R0, (DISK_READ as i16).into()),
+            StoreIndirect(R0, 7.into()), // DKCR = command
+            LoadIndirect(R2, 7.into()),  // wait until the transfer is done
+            Branch(0b011.into(), (-2).into()),
+            Instruction::trap_halt(),
+        ],
+        &[
+            1,      // sector
+            0x4000, // buffer
+            DKSN as i16,
+            DKBA as i16,
+            DKCR as i16,
+            DKSR as i16,
+        ],
+    );
+    machine.run_until_halt();
+    assert_eq!(machine.registers.get(Register::R2), i16::MIN | 1);
+
+    // the status can't be written
+    machine.set_memory_at(DKSR, 0).unwrap();
+    assert_eq!(machine.memory[DKSR], i16::MIN | 1);
+
+    // clearing DKCR isn't a command, the last one's error stays
+    machine.set_memory_at(DKCR, 0).unwrap();
+    assert_eq!(machine.memory[DKSR], i16::MIN | 1);
+
+    // nor is any other unknown value
+    machine.set_memory_at(DKCR, 3).unwrap();
+    assert_eq!(machine.memory[DKSR], i16::MIN | 1);
+    assert!(!machine.device::<Disk>().unwrap().ticks());
+}
+
+#[test]
+fn disk_respects_memory_protection() {
+    use Instruction::*;
+    use Register::*;
+
+    let image = vec![0x12; SECTOR_WORDS * 2];
+
+    // a user program can't read over the trap vector table
+    let mut machine = machine_with_devices(
+        vec![Box::new(
+            Disk::new(std::io::Cursor::new(image.clone())).unwrap(),
+        )],
+        true,
+        &[
+            Load(R0, 9.into()),
+            StoreIndirect(R0, 10.into()), // DKSN
+            Load(R0, 8.into()),
+            StoreIndirect(R0, 9.into()), // DKBA
+            AndImmediate(R0, R0, 0.into()),
+            AddImmediate(R0, R0, (DISK_READ as i16).into()),
+            StoreIndirect(R0, 7.into()), // DKCR = command
+            LoadIndirect(R2, 7.into()),  // wait until the transfer is done
+            Branch(0b011.into(), (-2).into()),
+            Instruction::trap_halt(),
+        ],
+        &[
+            0,      // sector
+            0x0020, // buffer
+            DKSN as i16,
+            DKBA as i16,
+            DKCR as i16,
+            DKSR as i16,
+        ],
+    );
+    let halt_vector = machine.memory[0x0025];
+
+    machine.run_until_halt();
+    assert_eq!(machine.registers.get(Register::R2), i16::MIN | 1);
+    assert_eq!(machine.memory[0x0025], halt_vector);
+
+    // the privilege counts when the command is issued, not when it runs
+    let mut machine = Machine::new_x3000(&[]);
+    machine.attach_device(Disk::new(std::io::Cursor::new(image)).unwrap());
+    machine.set_privilege(PrivilegeMode::Supervisor);
+    machine.set_memory_at(DKSN, 0).unwrap();
+    machine.set_memory_at(DKBA, 0x0200).unwrap();
+    machine.set_memory_at(DKCR, DISK_READ as i16).unwrap();
+    machine.set_privilege(PrivilegeMode::User);
+
+    machine.step();
+    assert_eq!(machine.memory[DKSR], i16::MIN);
+    assert_eq!(machine.memory[0x0200], 0x1212);
+}
+
+#[test]
+fn disk_user_accessible() {
+    let disk = Disk::new(std::io::Cursor::new(vec![])).unwrap();
+    let mut machine = machine_with_devices(vec![Box::new(disk)], true, &[], &[]);
+    assert_eq!(machine.privilege, PrivilegeMode::User);
+
+    for register in [DKSR, DKCR, DKSN, DKBA] {
+        assert!(machine.get_memory_at(register).is_ok());
+    }
+
+    // the rest of device memory stays protected
+    assert!(machine.set_memory_at(TMI, 1).is_err());
+}
+
+#[test]
+fn breakpoints() {
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+            Instruction::AddImmediate(Register::R1, Register::R0, (-5).into()),
+            Instruction::Branch(0b100.into(), (-3).into()),
+            Instruction::Store(Register::R0, 1.into()),
+            Instruction::trap_halt(),
+        ],
+    );
+    machine.set_breakpoint(0x3001);
+
+    for i in 1..=5 {
+        assert_eq!(machine.run(), StopReason::Breakpoint(0x3001));
+        assert_eq!(machine.registers.get(Register::R0), i);
+    }
+
+    assert!(machine.remove_breakpoint(0x3001));
+    assert_eq!(machine.run(), StopReason::Halted);
+    assert_eq!(machine.run(), StopReason::Halted);
+}
+
+#[test]
+fn conditional_breakpoints() {
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+            Instruction::AddImmediate(Register::R1, Register::R0, (-5).into()),
+            Instruction::Branch(0b100.into(), (-3).into()),
+            Instruction::Store(Register::R0, 1.into()),
+            Instruction::trap_halt(),
+        ],
+    );
+    machine.set_conditional_breakpoint(0x3000, |m| m.registers.get(Register::R0) == 3);
+
+    assert_eq!(machine.run(), StopReason::Breakpoint(0x3000));
+    assert_eq!(machine.registers.get(Register::R0), 3);
+    assert_eq!(machine.run(), StopReason::Halted);
+}
+
+#[test]
+fn breakpoint_at_label() {
+    let program = r#"
+LC-3 OBJ FILE
+
+.TEXT
+3000
+3
+1021
+0FFE
+F025
+
+.SYMBOL
+ADDR | EXT | LABEL
+3001 |   0 | LOOP
+"#;
+
+    let mut machine = Machine::new_x3000(&[]);
+    machine.load_assembly(&read_complex::read(program.as_bytes()));
+
+    assert_eq!(machine.symbol_address("LOOP"), Some(0x3001));
+    assert_eq!(machine.set_breakpoint_at_label("Loop"), Some(0x3001));
+    assert_eq!(machine.set_breakpoint_at_label("missing"), None);
+
+    assert_eq!(machine.run(), StopReason::Breakpoint(0x3001));
+    assert_eq!(machine.registers.get(Register::R0), 1);
+}
+
+#[test]
+fn watchpoints() {
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+            Instruction::AddImmediate(Register::R1, Register::R0, (-5).into()),
+            Instruction::Branch(0b100.into(), (-3).into()),
+            Instruction::Store(Register::R0, 1.into()),
+            Instruction::trap_halt(),
+        ],
+    );
+    machine.set_watchpoint(0x3005, WatchKind::Write);
+
+    assert_eq!(
+        machine.run(),
+        StopReason::Watchpoint(WatchpointHit {
+            address: 0x3005,
+            access: MemoryAccess::Write(5),
+            pc: 0x3003,
+        })
+    );
+    assert_eq!(machine.memory[0x3005], 5);
+
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::Load(Register::R0, 2.into()),
+            Instruction::Store(Register::R0, 1.into()),
+            Instruction::trap_halt(),
+        ],
+    );
+    machine.set_memory_at_unchecked(0x3003, 42);
+    machine.set_watchpoint(0x3003, WatchKind::Read);
+
+    assert_eq!(
+        machine.run(),
+        StopReason::Watchpoint(WatchpointHit {
+            address: 0x3003,
+            access: MemoryAccess::Read(42),
+            pc: 0x3000,
+        })
+    );
+
+    // writes are not watched
+    assert_eq!(machine.run(), StopReason::Halted);
+}
+
+#[test]
+fn stop_on_exception() {
+    let mut machine = Machine::new_x3000(&[Instruction::Reserved, Instruction::trap_halt()]);
+    machine.debugger.break_on_exceptions = true;
+
+    assert_eq!(machine.run(), StopReason::Exception(0x1));
+    assert_eq!(machine.privilege, PrivilegeMode::Supervisor);
+}
+
+#[test]
+fn instruction_limit() {
+    let mut machine = Machine::new_x3000(&[Instruction::Branch(0b111.into(), (-1).into())]);
+    machine.debugger.instruction_limit = Some(100);
+
+    assert_eq!(machine.run(), StopReason::InstructionLimit);
+    assert_eq!(machine.instruction_count, 100);
+}
+
+#[test]
+fn run_for() {
+    let mut machine = Machine::new_x3000(&[Instruction::Branch(0b111.into(), (-1).into())]);
+
+    assert_eq!(machine.run_for(100), StopReason::InstructionLimit);
+    assert_eq!(machine.instruction_count, 100);
+
+    // counted from where the last run stopped
+    assert_eq!(machine.run_for(50), StopReason::InstructionLimit);
+    assert_eq!(machine.instruction_count, 150);
+    assert_eq!(machine.debugger.instruction_limit, None);
+
+    let program = [
+        Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+        Instruction::AddImmediate(Register::R1, Register::R0, (-5).into()),
+        Instruction::Branch(0b100.into(), (-3).into()),
+        Instruction::Store(Register::R0, 1.into()),
+        Instruction::trap_halt(),
+    ];
+    let mut machine = Machine::new(0x3000, false, false, &program);
+    assert_eq!(machine.run_for(10_000), StopReason::Halted);
+    assert_eq!(machine.memory[0x3005], 5);
+
+    // the debugger's stop conditions still apply
+    let mut machine = Machine::new(0x3000, false, false, &program);
+    machine.set_breakpoint(0x3003);
+    assert_eq!(machine.run_for(10_000), StopReason::Breakpoint(0x3003));
+}
+
+#[test]
+fn run_until() {
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+            Instruction::AddImmediate(Register::R1, Register::R0, (-5).into()),
+            Instruction::Branch(0b100.into(), (-3).into()),
+            Instruction::Store(Register::R0, 1.into()),
+            Instruction::trap_halt(),
+        ],
+    );
+
+    let reason = machine.run_until(|machine| machine.registers.get(Register::R0) == 3);
+    assert_eq!(reason, StopReason::Predicate);
+    assert_eq!(machine.ip, 0x3001);
+
+    // checked before the first instruction too
+    assert_eq!(machine.run_until(|_| true), StopReason::Predicate);
+    assert_eq!(machine.ip, 0x3001);
+
+    // both bounds at once, whichever comes first
+    machine.debugger.instruction_limit = Some(machine.instruction_count + 2);
+    machine.debugger.deadline = Some(Instant::now() + Duration::from_secs(60));
+    assert_eq!(machine.run(), StopReason::InstructionLimit);
+    assert_eq!(machine.ip, 0x3000);
+}
+
+#[test]
+fn run_deadline() {
+    let mut machine = Machine::new_x3000(&[Instruction::Branch(0b111.into(), (-1).into())]);
+
+    let start = Instant::now();
+    assert_eq!(
+        machine.run_for_duration(Duration::from_millis(20)),
+        StopReason::Deadline
+    );
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert_eq!(machine.debugger.deadline, None);
+
+    // a deadline that already passed stops right away
+    let count = machine.instruction_count;
+    assert_eq!(machine.run_until_deadline(start), StopReason::Deadline);
+    assert!(machine.instruction_count - count <= 256);
+
+    // a timeout past what Instant can hold means no deadline
+    machine.debugger.instruction_limit = Some(machine.instruction_count + 10);
+    assert_eq!(
+        machine.run_for_duration(Duration::MAX),
+        StopReason::InstructionLimit
+    );
+}
+
+#[test]
+fn self_modifying_code() {
+    use Instruction::*;
+    use Register::*;
+
+    // the first pass replaces the increment by one with an increment by five
+    let mut machine = Machine::new_x3000(&[
+        AddImmediate(R4, R4, 1.into()),
+        Load(R2, 5.into()),
+        Store(R2, (-3).into()),
+        AddImmediate(R0, R0, 1.into()),
+        AddImmediate(R3, R0, (-2).into()),
+        Branch(0b100.into(), (-6).into()),
+        Instruction::trap_halt(),
+    ]);
+    machine.set_memory_at_unchecked(0x3007, AddImmediate(R4, R4, 5.into()).encode() as i16);
+
+    machine.run_until_halt();
+    assert_eq!(machine.registers.get(R4), 6);
+}
+
+#[test]
+fn decoded_instructions_follow_writes() {
+    use Instruction::*;
+    use Register::*;
+
+    let mut machine = Machine::new_x3000(&[AddImmediate(R1, R1, 1.into())]);
+    let run_again = |machine: &mut Machine| {
+        machine.ip = 0x3000;
+        machine.step();
+        machine.registers.get(R1)
+    };
+
+    assert_eq!(run_again(&mut machine), 1);
+
+    machine
+        .set_memory_at(0x3000, AddImmediate(R1, R1, 2.into()).encode() as i16)
+        .unwrap();
+    assert_eq!(run_again(&mut machine), 3);
+
+    machine.set_memory_at_unchecked(0x3000, AddImmediate(R1, R1, 3.into()).encode() as i16);
+    assert_eq!(run_again(&mut machine), 6);
+
+    machine.set_span_at(0x3000, &[AddImmediate(R1, R1, 4.into()).encode() as i16]);
+    assert_eq!(run_again(&mut machine), 10);
+}
+
+// the next event that isn't display output
+fn next_stop(handle: &MachineHandle) -> Event {
+    loop {
+        match handle
+            .events()
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+        {
+            Event::Output(_) => {}
+            event => return event,
+        }
+    }
+}
+
+#[test]
+fn machine_handle_io() {
+    let handle = MachineHandle::spawn(Machine::new_x3000(&[
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_get_c(),
+        Instruction::trap_out(),
+        Instruction::trap_halt(),
+    ]));
+    handle.inject_key('h' as u16);
+    handle.inject_key('i' as u16);
+    handle.resume();
+
+    let mut output = String::new();
+    loop {
+        match handle
+            .events()
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+        {
+            Event::Output(data) => output.push((data as u8) as char),
+            Event::Halted => break,
+            event => panic!("unexpected {event:?}"),
+        }
+    }
+    assert_eq!(output, format!("hi{HALT_MESSAGE}"));
+
+    let machine = handle.join();
+    assert!(machine.halted);
+}
+
+#[test]
+fn machine_handle_control() {
+    let handle = MachineHandle::spawn(Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+            Instruction::AddImmediate(Register::R1, Register::R0, (-5).into()),
+            Instruction::Branch(0b100.into(), (-3).into()),
+            Instruction::Store(Register::R0, 1.into()),
+            Instruction::trap_halt(),
+        ],
+    ));
+    handle.set_breakpoint(0x3003);
+    handle.resume();
+
+    assert_eq!(
+        next_stop(&handle),
+        Event::Stopped {
+            pc: 0x3003,
+            cause: StopCause::Breakpoint
+        }
+    );
+    let (registers, pc) = handle.registers();
+    assert_eq!(registers.get(Register::R0), 5);
+    assert_eq!(pc, 0x3003);
+
+    handle.step();
+    assert_eq!(
+        next_stop(&handle),
+        Event::Stopped {
+            pc: 0x3004,
+            cause: StopCause::Stepped
+        }
+    );
+    assert_eq!(handle.read_memory(0x3005, 1), [5]);
+
+    handle.resume();
+    assert_eq!(next_stop(&handle), Event::Halted);
+
+    // a halted machine still answers, without running
+    let count = handle.inspect(|machine| machine.instruction_count);
+    handle.step();
+    assert_eq!(next_stop(&handle), Event::Halted);
+    handle.resume();
+    assert_eq!(next_stop(&handle), Event::Halted);
+    assert_eq!(handle.inspect(|machine| machine.instruction_count), count);
+
+    // an endless loop can still be paused
+    let handle = MachineHandle::spawn(Machine::new_x3000(&[Instruction::Branch(
+        0b111.into(),
+        (-1).into(),
+    )]));
+    handle.resume();
+    while handle.inspect(|machine| machine.instruction_count) < 10_000 {}
+    handle.pause();
+    assert_eq!(
+        next_stop(&handle),
+        Event::Stopped {
+            pc: 0x3000,
+            cause: StopCause::Paused
+        }
+    );
+}
+
+#[test]
+fn machine_handle_reports_run_stops() {
+    use Instruction::*;
+    use Register::*;
+
+    // the slice ends right where the breakpoint is
+    let handle = MachineHandle::spawn(Machine::new_x3000(&[
+        AddImmediate(R0, R0, 1.into()),
+        Branch(0b111.into(), (-2).into()),
+    ]));
+    handle.inspect(|machine| {
+        machine.set_conditional_breakpoint(0x3000, |machine| machine.registers.get(R0) == 512)
+    });
+    handle.resume();
+    assert_eq!(
+        next_stop(&handle),
+        Event::Stopped {
+            pc: 0x3000,
+            cause: StopCause::Breakpoint
+        }
+    );
+    assert_eq!(handle.inspect(|machine| machine.instruction_count), 1024);
+
+    handle.inspect(|machine| {
+        machine.debugger.instruction_limit = Some(machine.instruction_count + 3)
+    });
+    handle.resume();
+    assert_eq!(
+        next_stop(&handle),
+        Event::Stopped {
+            pc: 0x3001,
+            cause: StopCause::InstructionLimit
+        }
+    );
+
+    let handle = MachineHandle::spawn(Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+            Instruction::AddImmediate(Register::R1, Register::R0, (-5).into()),
+            Instruction::Branch(0b100.into(), (-3).into()),
+            Instruction::Store(Register::R0, 1.into()),
+            Instruction::trap_halt(),
+        ],
+    ));
+    handle.inspect(|machine| machine.set_watchpoint(0x3005, WatchKind::Write));
+    handle.resume();
+    assert_eq!(
+        next_stop(&handle),
+        Event::Stopped {
+            pc: 0x3004,
+            cause: StopCause::Watchpoint(WatchpointHit {
+                address: 0x3005,
+                access: MemoryAccess::Write(5),
+                pc: 0x3003
+            })
+        }
+    );
+
+    handle.resume();
+    assert_eq!(next_stop(&handle), Event::Halted);
+}
+
+#[test]
+fn step_back() {
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+            Instruction::AddImmediate(Register::R1, Register::R0, (-5).into()),
+            Instruction::Branch(0b100.into(), (-3).into()),
+            Instruction::Store(Register::R0, 1.into()),
+            Instruction::trap_halt(),
+        ],
+    );
+    machine.enable_history(1000);
+
+    machine.run_until_halt();
+    assert_eq!(machine.memory[0x3005], 5);
+
+    assert_eq!(machine.step_back(1), 1);
+    assert!(!machine.halted);
+
+    // out of the HALT routine
+    assert!(machine.run_back_to(0x3004));
+    assert_eq!(machine.privilege, PrivilegeMode::User);
+
+    // back to before the store
+    assert!(machine.run_back_to(0x3003));
+    assert_eq!(machine.memory[0x3005], 0);
+    assert!(!machine.memory.is_touched(0x3005));
+    assert_eq!(machine.registers.get(Register::R0), 5);
+
+    // all the way to the start
+    let steps = machine.history().unwrap().len();
+    assert_eq!(machine.step_back(steps + 10), steps);
+    assert_eq!(machine.ip, 0x3000);
+    assert_eq!(machine.instruction_count, 0);
+    assert_eq!(machine.registers.get(Register::R0), 0);
+    assert!(!machine.run_back_to(0x3000));
+
+    // and forwards again
+    machine.run_until_halt();
+    assert_eq!(machine.memory[0x3005], 5);
+}
+
+#[test]
+fn step_back_out_of_trap() {
+    let mut machine = Machine::new_x3000(&[
+        Instruction::AddImmediate(Register::R0, Register::R0, 7.into()),
+        Instruction::trap_out(),
+        Instruction::trap_halt(),
+    ]);
+    machine.enable_history(16);
+
+    machine.step();
+    machine.step();
+
+    // now inside the OUT routine
+    assert_eq!(machine.privilege, PrivilegeMode::Supervisor);
+    assert_eq!(machine.registers.get(Register::R6), BASIC_OS_SSP as i16 - 2);
+    machine.step(); // pushes R0 on the supervisor stack
+
+    assert!(machine.run_back_to(0x3001));
+    assert_eq!(machine.privilege, PrivilegeMode::User);
+    assert_eq!(machine.registers.get(Register::R6), 0xFE00u16 as i16);
+    assert_eq!(machine.condition_code, ConditionCode::Positive);
+    assert!(!machine.memory.is_touched(BASIC_OS_SSP - 1));
+
+    machine.set_privilege(PrivilegeMode::Supervisor);
+    assert_eq!(machine.registers.get(Register::R6), BASIC_OS_SSP as i16);
+}
+
+#[test]
+fn history_is_bounded() {
+    let mut machine = Machine::new_x3000(&[Instruction::Branch(0b111.into(), (-1).into())]);
+    machine.enable_history(3);
+
+    for _ in 0..10 {
+        machine.step();
+    }
+
+    assert_eq!(machine.history().unwrap().len(), 3);
+    assert_eq!(machine.step_back(5), 3);
+    assert_eq!(machine.instruction_count, 7);
+}
+
+#[test]
+fn step_back_restores_devices() {
+    use Instruction::*;
+    use Register::*;
+
+    // reads RNDR in a loop while the timer interrupts every 5 instructions
+    let mut machine = machine_with_devices(
+        vec![Box::new(Timer::default()), Box::new(Rng::new(3))],
+        false,
+        &[
+            Load(R1, 5.into()),
+            StoreIndirect(R1, 5.into()), // TMI = 5
+            Load(R1, 5.into()),
+            StoreIndirect(R1, 5.into()), // TMR = interrupt enable
+            LoadIndirect(R2, 5.into()),  // R2 = random
+            Branch(0b111.into(), (-2).into()),
+        ],
+        &[5, TMI as i16, 1 << 14, TMR as i16, RNDR as i16],
+    );
+
+    // ISR, acknowledges the timer
+    load_handler(
+        &mut machine,
+        TIMER_INTERRUPT_VECTOR,
+        0x4000,
+        &[LoadIndirect(R3, 1.into()), ReturnFromInterrupt],
+        &[TMR as i16],
+    );
+    machine.enable_history(100);
+
+    for _ in 0..4 {
+        machine.step();
+    }
+
+    let run = |machine: &mut Machine| {
+        (0..30)
+            .map(|_| {
+                machine.step();
+                (
+                    machine.ip,
+                    machine.registers.get(R2),
+                    machine.device::<Timer>().unwrap().remaining(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let remaining = machine.device::<Timer>().unwrap().remaining();
+    let first = run(&mut machine);
+    // steps that went into the ISR end after its first instruction
+    assert!(first.iter().any(|(ip, _, _)| *ip == 0x4001));
+
+    assert_eq!(machine.step_back(30), 30);
+    assert_eq!(machine.ip, 0x3004);
+    assert_eq!(machine.device::<Timer>().unwrap().remaining(), remaining);
+
+    // the same random numbers and interrupts again
+    assert_eq!(run(&mut machine), first);
+}
+
+// a sink the test can still read after handing it to the machine
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedBuffer {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[test]
+fn trace_text() {
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::AddImmediate(Register::R0, Register::R0, 5.into()),
+            Instruction::Store(Register::R0, 1.into()),
+            Instruction::Load(Register::R1, 0.into()),
+        ],
+    );
+    let sink = SharedBuffer::default();
+
+    machine.start_trace(sink.clone(), TraceFormat::Text);
+    for _ in 0..3 {
+        machine.step();
+    }
+    machine.stop_trace().unwrap();
+
+    // stopped, nothing more is written
+    machine.step();
+
+    assert_eq!(
+        sink.contents(),
+        "x3000 | x1025 | ADD R0, R0, #5 | R0=x0005 | | P | user\n\
+         x3001 | x3001 | ST R0, #1 | | w x3003=x0005 | P | user\n\
+         x3002 | x2200 | LD R1, #0 | R1=x0005 | r x3003=x0005 | P | user\n"
+    );
+}
+
+#[test]
+fn trace_json_lines() {
+    let mut machine = Machine::new(
+        0x3000,
+        false,
+        false,
+        &[
+            Instruction::AddImmediate(Register::R0, Register::R0, 5.into()),
+            Instruction::Store(Register::R0, 1.into()),
+            Instruction::Load(Register::R1, 0.into()),
+        ],
+    );
+    let sink = SharedBuffer::default();
+
+    machine.start_trace(sink.clone(), TraceFormat::JsonLines);
+    for _ in 0..4 {
+        machine.step();
+    }
+    machine.stop_trace().unwrap();
+
+    let contents = sink.contents();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(
+        lines[2],
+        "{\"pc\":12290,\"word\":8704,\"disassembly\":\"LD R1, #0\",\"registers\":{\"R1\":5},\
+         \"reads\":[{\"address\":12291,\"value\":5}],\"writes\":[],\"cc\":\"P\",\"privilege\":\"user\"}"
+    );
+
+    // x3003 holds 5, which decodes to BR with no flags
+    assert!(lines[3].contains("\"disassembly\":\"NOP #5\""));
+}
+
+#[test]
+fn trace_stack_pointer_switch() {
+    let mut machine = Machine::new_x3000(&[
+        Instruction::AddImmediate(Register::R6, Register::R6, (-1).into()),
+        Instruction::trap_out(),
+    ]);
+    let sink = SharedBuffer::default();
+
+    machine.start_trace(sink.clone(), TraceFormat::Text);
+    while machine.ip != 0x3002 {
+        machine.step();
+    }
+    machine.stop_trace().unwrap();
+
+    let contents = sink.contents();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert!(lines[0].contains("R6=xFDFF"));
+
+    // neither the TRAP nor the RTI write R6, they only switch stacks
+    assert!(lines[1].contains("OUT"));
+    assert!(!lines[1].contains("R6="));
+    assert!(lines.last().unwrap().contains("RTI"));
+    assert!(!lines.last().unwrap().contains("R6="));
+}
+
+#[test]
+fn snapshot_round_trip() {
+    // enables the timer and spins until the ISR has counted to 5
+    let mut machine = machine_with_devices(
+        vec![Box::new(Timer::default())],
+        false,
+        &[
+            Instruction::Load(Register::R1, 7.into()),
+            Instruction::StoreIndirect(Register::R1, 7.into()), // TMI = 20
+            Instruction::Load(Register::R1, 7.into()),
+            Instruction::StoreIndirect(Register::R1, 7.into()), // TMR = interrupt enable
+            Instruction::LoadIndirect(Register::R2, 7.into()),  // r2 = count
+            Instruction::AddImmediate(Register::R2, Register::R2, (-5).into()),
+            Instruction::Branch(0b100.into(), (-3).into()),
+            Instruction::trap_halt(),
+        ],
+        &[
+            20, // interval
+            TMI as i16,
+            1 << 14,
+            TMR as i16,
+            0x4100, // count address
+        ],
+    );
+
+    // ISR, increments the count
+    load_handler(
+        &mut machine,
+        TIMER_INTERRUPT_VECTOR,
+        0x4000,
+        &[
+            Instruction::Store(Register::R0, 5.into()),
+            Instruction::LoadIndirect(Register::R0, 5.into()),
+            Instruction::AddImmediate(Register::R0, Register::R0, 1.into()),
+            Instruction::StoreIndirect(Register::R0, 3.into()),
+            Instruction::Load(Register::R0, 1.into()),
+            Instruction::ReturnFromInterrupt,
+        ],
+        &[
+            0,      // saved R0
+            0x4100, // count address
+        ],
+    );
+
+    for _ in 0..57 {
+        machine.step();
+    }
+
+    // run into an interrupt, so the supervisor stack is in use
+    while machine.privilege == PrivilegeMode::User {
+        machine.step();
+    }
+
+    let mut snapshot = Vec::new();
+    machine.save_snapshot(&mut snapshot).unwrap();
+
+    // the program and the ISR come back with the memory
+    let mut restored = machine_with_devices(vec![Box::new(Timer::default())], false, &[], &[]);
+    restored.load_snapshot(snapshot.as_slice()).unwrap();
+
+    assert_eq!(restored.ip, machine.ip);
+    assert_eq!(restored.privilege, PrivilegeMode::Supervisor);
+    assert_eq!(restored.priority, machine.priority);
+    assert_eq!(restored.encode_psr(), machine.encode_psr());
+    assert_eq!(restored.instruction_count, machine.instruction_count);
+    assert_eq!(restored.registers.ssp(), machine.registers.ssp());
+    assert_eq!(restored.registers.usp(), machine.registers.usp());
+    assert_eq!(
+        restored.device::<Timer>().unwrap().remaining(),
+        machine.device::<Timer>().unwrap().remaining()
+    );
+    assert!(restored.memory.touched().eq(machine.memory.touched()));
+
+    machine.run_until_halt();
+    restored.run_until_halt();
+
+    assert_eq!(restored.instruction_count, machine.instruction_count);
+    assert_eq!(restored.memory[0x4100], machine.memory[0x4100]);
+    for i in 0..8u8 {
+        assert_eq!(
+            restored.registers.get(i.into()),
+            machine.registers.get(i.into())
+        );
+    }
+}
+
+#[test]
+fn snapshot_keeps_memory_protection() {
+    let machine = Machine::builder()
+        .protect_range(0x4000..=0x40FF)
+        .mpr(!(1 << 5))
+        .build();
+
+    let mut snapshot = Vec::new();
+    machine.save_snapshot(&mut snapshot).unwrap();
+
+    let mut restored = Machine::new_x3000(&[]);
+    restored.load_snapshot(snapshot.as_slice()).unwrap();
+
+    assert_eq!(restored.protected_ranges, [0x4000..=0x40FF]);
+    assert!(restored.set_memory_at(0x4000, 1).is_err());
+    assert!(restored.set_memory_at(0x5000, 1).is_err());
+    assert!(restored.set_memory_at(0x6000, 1).is_ok());
+}
+
+#[test]
+fn snapshot_keeps_memory_init() {
+    let mut machine = Machine::new_x3000(&[]);
+    machine.set_memory_init(MemoryInit::Random(42));
+
+    let mut snapshot = Vec::new();
+    machine.save_snapshot(&mut snapshot).unwrap();
+
+    let mut restored = Machine::new_x3000(&[]);
+    restored.load_snapshot(snapshot.as_slice()).unwrap();
+
+    assert_eq!(restored.memory.init(), MemoryInit::Random(42));
+    assert!((0x3000..0x3100).all(|i| restored.memory[i] == machine.memory[i]));
+}
+
+#[test]
+fn snapshot_device_names() {
+    let mut machine = Machine::new_x3000(&[]);
+    machine.attach_device(Timer::default());
+    machine.attach_device(Counter::default());
+
+    let mut snapshot = Vec::new();
+    machine.save_snapshot(&mut snapshot).unwrap();
+
+    // written as length then name, the same on every build
+    for name in ["timer", "counter"] {
+        let mut entry = (name.len() as u32).to_be_bytes().to_vec();
+        entry.extend_from_slice(name.as_bytes());
+        assert!(snapshot.windows(entry.len()).any(|window| window == entry));
+    }
+}
+
+#[test]
+fn snapshot_rejects_garbage() {
+    let mut machine = Machine::new_x3000(&[]);
+
+    assert!(machine.load_snapshot(&b"not a snapshot"[..]).is_err());
+
+    let mut snapshot = Vec::new();
+    machine.save_snapshot(&mut snapshot).unwrap();
+    snapshot.truncate(snapshot.len() / 2);
+
+    assert!(machine.load_snapshot(snapshot.as_slice()).is_err());
+    assert_eq!(machine.ip, 0x3000); // untouched after a failed load
+
+    // the last device state claims to be 4 GiB long
+    let mut snapshot = Vec::new();
+    machine.save_snapshot(&mut snapshot).unwrap();
+    let len = snapshot.len();
+    snapshot[len - 4..].copy_from_slice(&u32::MAX.to_be_bytes());
+
+    assert!(machine.load_snapshot(snapshot.as_slice()).is_err());
+}
+
+// `program` at x3000 with its `data` right behind it, then `devices` attached.
+// `protect` turns on both system and device memory protection
+pub fn machine_with_devices(
+    devices: Vec<Box<dyn Device>>,
+    protect: bool,
+    program: &[Instruction],
+    data: &[i16],
+) -> Machine {
+    let mut machine = Machine::new(0x3000, protect, protect, program);
+    machine.set_span_at(0x3000 + program.len() as u16, data);
+
+    for device in devices {
+        machine.attach_boxed_device(device);
+    }
+    machine
+}
+
+// an interrupt handler at `address`, with its `data` right behind the code
+pub fn load_handler(
+    machine: &mut Machine,
+    vector: u8,
+    address: u16,
+    code: &[Instruction],
+    data: &[i16],
+) {
+    machine.set_memory_at_unchecked(0x0100 + vector as u16, address as i16);
+
+    let code: Vec<i16> = code.iter().map(|instr| instr.encode() as i16).collect();
+    machine.set_span_at(address, &code);
+    machine.set_span_at(address + code.len() as u16, data);
+}
+
+pub fn run_given_in_out(machine: &mut Machine, input: &[u8]) -> String {
+    let mut console = BufferConsole::new(input);
+    machine.run_with_console(&mut console).unwrap();
+    console.take_output()
 }
@@ -0,0 +1,109 @@
+use std::ops::RangeInclusive;
+
+use crate::vm::devices::keyboard::DEFAULT_TYPE_AHEAD;
+use crate::vm::devices::protection::MPR_ALL_BLOCKS;
+use crate::vm::devices::{MPR, OverflowPolicy};
+use crate::vm::instructions::Instruction;
+use crate::vm::machine::Machine;
+
+// Configures a machine before it's created, for anything `Machine::new` doesn't take.
+pub struct MachineBuilder {
+    pc: u16,
+    instructions: Vec<Instruction>,
+    protect_system_memory: bool,
+    protect_device_memory: bool,
+    protected_ranges: Vec<RangeInclusive<u16>>,
+    mpr: u16,
+    type_ahead: (usize, OverflowPolicy),
+    os: bool,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        Self {
+            pc: 0x3000,
+            instructions: Vec::new(),
+            protect_system_memory: true,
+            protect_device_memory: true,
+            protected_ranges: Vec::new(),
+            mpr: MPR_ALL_BLOCKS,
+            type_ahead: (DEFAULT_TYPE_AHEAD, OverflowPolicy::default()),
+            os: true,
+        }
+    }
+}
+
+impl MachineBuilder {
+    // where the instructions are placed and execution starts, x3000 by default
+    pub fn pc(mut self, pc: u16) -> Self {
+        self.pc = pc;
+        self
+    }
+
+    pub fn instructions(mut self, instructions: &[Instruction]) -> Self {
+        self.instructions = instructions.to_vec();
+        self
+    }
+
+    // x0000 to x2FFF, protected by default
+    pub fn protect_system_memory(mut self, protect: bool) -> Self {
+        self.protect_system_memory = protect;
+        self
+    }
+
+    // xFE00 to xFFFF, protected by default
+    pub fn protect_device_memory(mut self, protect: bool) -> Self {
+        self.protect_device_memory = protect;
+        self
+    }
+
+    // keeps user programs out of the range, on top of the other protection.
+    // registers of user accessible devices stay usable
+    pub fn protect_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.protected_ranges.push(range);
+        self
+    }
+
+    // initial Memory Protection Register, bit n opens the 4K block at n * x1000
+    pub fn mpr(mut self, mpr: u16) -> Self {
+        self.mpr = mpr;
+        self
+    }
+
+    // how many keys the keyboard holds behind KBDR, and which key is lost once it's full
+    pub fn type_ahead(mut self, capacity: usize, overflow: OverflowPolicy) -> Self {
+        self.type_ahead = (capacity, overflow);
+        self
+    }
+
+    // leaves out the basic OS, e.g. to load another one with `load_os`
+    pub fn without_os(mut self) -> Self {
+        self.os = false;
+        self
+    }
+
+    pub fn build(self) -> Machine {
+        let mut machine = Machine::new_without_os(
+            self.pc,
+            self.protect_system_memory,
+            self.protect_device_memory,
+            &self.instructions,
+        );
+        if self.os {
+            machine.load_basic_os();
+        }
+
+        let (capacity, overflow) = self.type_ahead;
+        machine.set_type_ahead(capacity, overflow);
+
+        machine.protected_ranges = self.protected_ranges;
+        machine.set_memory_at_unchecked(MPR, self.mpr as i16);
+        machine
+    }
+}
+
+impl Machine {
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder::default()
+    }
+}
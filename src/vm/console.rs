@@ -0,0 +1,272 @@
+use std::collections::VecDeque;
+use std::io::{self, BufReader, Read, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use crate::vm::machine::Machine;
+
+// Where the keyboard gets its keys from and where the display's output goes.
+// `Machine::run_with_console` drives one, so hosts don't have to juggle
+// `set_keyboard_key`, `poll_display_data` and `step` themselves.
+pub trait Console {
+    // the next key, if one is available. only asked for while the keyboard has room for it,
+    // so a key that is handed over is never lost
+    fn read_key(&mut self) -> io::Result<Option<u16>>;
+
+    // a character the program wrote to the display
+    fn write_char(&mut self, data: u16) -> io::Result<()>;
+
+    // checked before every instruction, the run stops once this returns true
+    fn stop_requested(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConsoleExit {
+    Halted,
+
+    // the console asked to stop, the machine can be run again
+    Stopped,
+}
+
+impl Machine {
+    // takes the console's keys and gives it the display output, then executes one instruction
+    pub fn step_with_console(&mut self, console: &mut dyn Console) -> io::Result<()> {
+        while self.keyboard_has_room()
+            && let Some(key) = console.read_key()?
+        {
+            self.set_keyboard_key(key);
+        }
+
+        if let Some(data) = self.poll_display_data() {
+            console.write_char(data)?;
+        }
+
+        self.step();
+        Ok(())
+    }
+
+    // passes on whatever the display still holds, e.g. the end of the halt message
+    pub fn flush_console(&mut self, console: &mut dyn Console) -> io::Result<()> {
+        while let Some(data) = self.poll_display_data() {
+            console.write_char(data)?;
+        }
+        console.flush()
+    }
+
+    pub fn run_with_console(&mut self, console: &mut dyn Console) -> io::Result<ConsoleExit> {
+        let mut exit = ConsoleExit::Halted;
+
+        while !self.halted {
+            if console.stop_requested()? {
+                exit = ConsoleExit::Stopped;
+                break;
+            }
+
+            self.step_with_console(console)?;
+        }
+
+        self.flush_console(console)?;
+        Ok(exit)
+    }
+}
+
+// Input given up front, output collected in memory. Useful for tests and graders.
+#[derive(Default, Clone, Debug)]
+pub struct BufferConsole {
+    input: VecDeque<u16>,
+    output: String,
+}
+
+impl BufferConsole {
+    pub fn new(input: &[u8]) -> Self {
+        Self {
+            input: input.iter().map(|&key| key as u16).collect(),
+            output: String::new(),
+        }
+    }
+
+    pub fn push_input(&mut self, input: &[u8]) {
+        self.input.extend(input.iter().map(|&key| key as u16));
+    }
+
+    // keys the program hasn't read yet
+    pub fn remaining_input(&self) -> usize {
+        self.input.len()
+    }
+
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.output)
+    }
+}
+
+impl Console for BufferConsole {
+    fn read_key(&mut self) -> io::Result<Option<u16>> {
+        Ok(self.input.pop_front())
+    }
+
+    fn write_char(&mut self, data: u16) -> io::Result<()> {
+        self.output.push((data as u8) as char);
+        Ok(())
+    }
+}
+
+// Keys come byte by byte from a reader and output goes to a writer, e.g. stdin and stdout
+// when the VM is part of a shell pipeline. The reader runs on its own thread, so the
+// machine keeps going while no input is available.
+pub struct PipeConsole<W: Write> {
+    input: Receiver<io::Result<u8>>,
+    output: W,
+}
+
+impl PipeConsole<io::Stdout> {
+    pub fn stdio() -> Self {
+        Self::new(io::stdin(), io::stdout())
+    }
+}
+
+impl<W: Write> PipeConsole<W> {
+    pub fn new(input: impl Read + Send + 'static, output: W) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            for byte in BufReader::new(input).bytes() {
+                let failed = byte.is_err();
+                if sender.send(byte).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            input: receiver,
+            output,
+        }
+    }
+
+    pub fn into_output(self) -> W {
+        self.output
+    }
+}
+
+impl<W: Write> Console for PipeConsole<W> {
+    fn read_key(&mut self) -> io::Result<Option<u16>> {
+        match self.input.try_recv() {
+            Ok(byte) => byte.map(|byte| Some(byte as u16)),
+            // at the end of the input the keyboard just stays empty
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => Ok(None),
+        }
+    }
+
+    fn write_char(&mut self, data: u16) -> io::Result<()> {
+        self.output.write_all(&[data as u8])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// An interactive terminal in raw mode, Ctrl+C stops the run.
+// Raw mode is left again when the console is dropped.
+#[cfg(feature = "crossterm")]
+pub struct TerminalConsole {
+    // keys read while looking for Ctrl+C
+    pending: VecDeque<u16>,
+    stop: bool,
+
+    // whether `stop_requested` polled the terminal since the last `read_key`,
+    // so a step polls it once rather than for each of them
+    polled: bool,
+}
+
+#[cfg(feature = "crossterm")]
+impl TerminalConsole {
+    pub fn new() -> io::Result<Self> {
+        crossterm::terminal::enable_raw_mode()?;
+
+        Ok(Self {
+            pending: VecDeque::new(),
+            stop: false,
+            polled: false,
+        })
+    }
+
+    // runs `f` with the terminal back in its normal mode, e.g. to print warnings
+    pub fn suspend<R>(&mut self, f: impl FnOnce() -> R) -> io::Result<R> {
+        crossterm::terminal::disable_raw_mode()?;
+        let result = f();
+        crossterm::terminal::enable_raw_mode()?;
+        Ok(result)
+    }
+
+    fn poll_events(&mut self) -> io::Result<()> {
+        use crossterm::event::{self, KeyCode, KeyModifiers};
+        use std::time::Duration;
+
+        while event::poll(Duration::ZERO)? {
+            let Some(key_event) = event::read()?.as_key_press_event() else {
+                continue;
+            };
+
+            if key_event.code == KeyCode::Char('c')
+                && key_event.modifiers.contains(KeyModifiers::CONTROL)
+            {
+                self.stop = true;
+            } else if key_event.code == KeyCode::Enter {
+                self.pending.push_back('\n' as u16);
+            } else if let Some(char) = key_event.code.as_char() {
+                self.pending.push_back(char as u16);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "crossterm")]
+impl Console for TerminalConsole {
+    fn read_key(&mut self) -> io::Result<Option<u16>> {
+        if self.pending.is_empty() && !std::mem::take(&mut self.polled) {
+            self.poll_events()?;
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    fn write_char(&mut self, data: u16) -> io::Result<()> {
+        let mut stdout = io::stdout();
+
+        // raw mode doesn't return the cursor on a newline
+        if data == '\n' as u16 {
+            stdout.write_all(b"\r\n")?;
+        } else {
+            stdout.write_all(&[data as u8])?;
+        }
+        stdout.flush()
+    }
+
+    fn stop_requested(&mut self) -> io::Result<bool> {
+        self.poll_events()?;
+        self.polled = true;
+        Ok(std::mem::take(&mut self.stop))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+#[cfg(feature = "crossterm")]
+impl Drop for TerminalConsole {
+    fn drop(&mut self) {
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
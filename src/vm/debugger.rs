@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::vm::machine::Machine;
+
+pub type BreakpointCondition = Box<dyn Fn(&Machine) -> bool + Send>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn watches_reads(self) -> bool {
+        matches!(self, WatchKind::Read | WatchKind::ReadWrite)
+    }
+
+    fn watches_writes(self) -> bool {
+        matches!(self, WatchKind::Write | WatchKind::ReadWrite)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryAccess {
+    Read(i16),
+    Write(i16),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WatchpointHit {
+    pub address: u16,
+    pub access: MemoryAccess,
+    // address of the instruction that made the access
+    pub pc: u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum StopReason {
+    Halted,
+    // address of the breakpoint, the instruction there has not executed yet
+    Breakpoint(u16),
+    Watchpoint(WatchpointHit),
+    // exception vector, only reported while `break_on_exceptions` is set
+    Exception(u8),
+    InstructionLimit,
+    Deadline,
+    // the predicate given to `run_until` held
+    Predicate,
+}
+
+// the clock is only read this often, it's slower than most instructions
+const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: HashMap<u16, Option<BreakpointCondition>>,
+    watchpoints: HashMap<u16, WatchKind>,
+
+    pub break_on_exceptions: bool,
+    // `run` stops once `Machine::instruction_count` reaches this
+    pub instruction_limit: Option<u64>,
+    // or once this passes
+    pub deadline: Option<Instant>,
+
+    watchpoint_hit: Option<WatchpointHit>,
+    exception_hit: Option<u8>,
+}
+
+impl Debugger {
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (u16, WatchKind)> + '_ {
+        self.watchpoints
+            .iter()
+            .map(|(address, kind)| (*address, *kind))
+    }
+
+    pub(crate) fn record_exception(&mut self, vector: u8) {
+        self.exception_hit = Some(vector);
+    }
+
+    pub(crate) fn check_read(&mut self, address: u16, value: i16, pc: u16) {
+        if let Some(kind) = self.watchpoints.get(&address)
+            && kind.watches_reads()
+        {
+            self.watchpoint_hit = Some(WatchpointHit {
+                address,
+                access: MemoryAccess::Read(value),
+                pc,
+            });
+        }
+    }
+
+    pub(crate) fn check_write(&mut self, address: u16, value: i16, pc: u16) {
+        if let Some(kind) = self.watchpoints.get(&address)
+            && kind.watches_writes()
+        {
+            self.watchpoint_hit = Some(WatchpointHit {
+                address,
+                access: MemoryAccess::Write(value),
+                pc,
+            });
+        }
+    }
+
+    pub(crate) fn has_watchpoints(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+}
+
+impl Machine {
+    pub fn set_breakpoint(&mut self, address: u16) {
+        self.debugger.breakpoints.insert(address, None);
+    }
+
+    // breaks at `address` only when `condition` holds, e.g. `|m| m.registers.get(Register::R0) == 5`
+    pub fn set_conditional_breakpoint(
+        &mut self,
+        address: u16,
+        condition: impl Fn(&Machine) -> bool + Send + 'static,
+    ) {
+        self.debugger
+            .breakpoints
+            .insert(address, Some(Box::new(condition)));
+    }
+
+    // returns the address of the label, if the symbol is loaded
+    pub fn set_breakpoint_at_label(&mut self, label: &str) -> Option<u16> {
+        let address = self.symbol_address(label)?;
+        self.set_breakpoint(address);
+        Some(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.debugger.breakpoints.remove(&address).is_some()
+    }
+
+    pub fn set_watchpoint(&mut self, address: u16, kind: WatchKind) {
+        self.debugger.watchpoints.insert(address, kind);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) -> bool {
+        self.debugger.watchpoints.remove(&address).is_some()
+    }
+
+    pub fn is_breakpoint_hit(&self) -> bool {
+        match self.debugger.breakpoints.get(&self.ip) {
+            Some(Some(condition)) => condition(self),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    // whether the instruction limit or deadline stops the machine before its next instruction,
+    // for hosts running their own loop
+    pub fn limit_reached(&self) -> Option<StopReason> {
+        if let Some(limit) = self.debugger.instruction_limit
+            && self.instruction_count >= limit
+        {
+            return Some(StopReason::InstructionLimit);
+        }
+
+        if let Some(deadline) = self.debugger.deadline
+            && self
+                .instruction_count
+                .is_multiple_of(DEADLINE_CHECK_INTERVAL)
+            && Instant::now() >= deadline
+        {
+            return Some(StopReason::Deadline);
+        }
+
+        None
+    }
+
+    // Runs until the machine halts or one of the debugger's stop conditions is met.
+    // A breakpoint at the current instruction is stepped over, so calling `run` again resumes.
+    pub fn run(&mut self) -> StopReason {
+        self.run_until(|_| false)
+    }
+
+    // `run`, also stopping once `predicate` holds.
+    // The predicate is checked before every instruction, including the first.
+    pub fn run_until(&mut self, predicate: impl FnMut(&Machine) -> bool) -> StopReason {
+        self.continue_until(true, predicate)
+    }
+
+    // `run_until`, only stepping over a breakpoint at the current instruction if
+    // `step_over_breakpoint`, so a host running in slices doesn't miss one a slice ended on
+    pub(crate) fn continue_until(
+        &mut self,
+        step_over_breakpoint: bool,
+        mut predicate: impl FnMut(&Machine) -> bool,
+    ) -> StopReason {
+        let mut first = step_over_breakpoint;
+
+        loop {
+            if self.halted {
+                return StopReason::Halted;
+            }
+
+            if let Some(reason) = self.limit_reached() {
+                return reason;
+            }
+
+            if predicate(self) {
+                return StopReason::Predicate;
+            }
+
+            if !first && !self.debugger.breakpoints.is_empty() && self.is_breakpoint_hit() {
+                return StopReason::Breakpoint(self.ip);
+            }
+            first = false;
+
+            self.debugger.watchpoint_hit = None;
+            self.debugger.exception_hit = None;
+
+            self.step();
+
+            if let Some(hit) = self.debugger.watchpoint_hit.take() {
+                return StopReason::Watchpoint(hit);
+            }
+
+            if let Some(vector) = self.debugger.exception_hit.take()
+                && self.debugger.break_on_exceptions
+            {
+                return StopReason::Exception(vector);
+            }
+        }
+    }
+
+    // `run` for at most `max_instructions` more instructions
+    pub fn run_for(&mut self, max_instructions: u64) -> StopReason {
+        let limit = self.instruction_count.saturating_add(max_instructions);
+        let previous = self.debugger.instruction_limit;
+        self.debugger.instruction_limit = Some(previous.map_or(limit, |p| p.min(limit)));
+
+        let reason = self.run();
+        self.debugger.instruction_limit = previous;
+        reason
+    }
+
+    // `run` until `deadline` at the latest
+    pub fn run_until_deadline(&mut self, deadline: Instant) -> StopReason {
+        let previous = self.debugger.deadline;
+        self.debugger.deadline = Some(previous.map_or(deadline, |p| p.min(deadline)));
+
+        let reason = self.run();
+        self.debugger.deadline = previous;
+        reason
+    }
+
+    pub fn run_for_duration(&mut self, timeout: Duration) -> StopReason {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.run_until_deadline(deadline),
+            // too far in the future to ever be reached
+            None => self.run(),
+        }
+    }
+}
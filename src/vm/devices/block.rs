@@ -0,0 +1,189 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::vm::devices::{DKBA, DKCR, DKSN, DKSR, Device};
+use crate::vm::machine::{Machine, PrivilegeMode};
+
+// words per sector, stored big endian in the image like object files
+pub const SECTOR_WORDS: usize = 256;
+const SECTOR_BYTES: u64 = SECTOR_WORDS as u64 * 2;
+
+pub const DISK_READ: u16 = 1;
+pub const DISK_WRITE: u16 = 2;
+
+// Block storage backed by a host image, moving whole sectors to and from memory.
+//
+// DKSN selects the sector and DKBA is the address of the sector sized buffer in memory.
+// Writing DISK_READ or DISK_WRITE to DKCR starts the transfer, which keeps the device busy
+// until the end of the instruction. Any other value written to DKCR, e.g. 0 to clear it, is
+// ignored. The transfer fails if the buffer overlaps memory that is
+// protected for whoever wrote DKCR, so user programs can't overwrite the OS through it.
+// DKSR is the status register:
+//   bit 15 is set while the device is ready for a command
+//   bit 0 is set when the last command failed, e.g. the sector is past the end of the image
+pub struct BlockDevice<S> {
+    storage: S,
+    sectors: u16,
+    // the command in progress and the privilege it was issued with
+    command: Option<(u16, PrivilegeMode)>,
+    failed: bool,
+}
+
+impl BlockDevice<File> {
+    // the image keeps its size, a partial sector at the end is left unused
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(OpenOptions::new().read(true).write(true).open(path)?)
+    }
+}
+
+impl<S: Read + Write + Seek> BlockDevice<S> {
+    pub fn new(mut storage: S) -> io::Result<Self> {
+        let len = storage.seek(SeekFrom::End(0))?;
+
+        Ok(Self {
+            storage,
+            sectors: (len / SECTOR_BYTES).min(u16::MAX as u64) as u16,
+            command: None,
+            failed: false,
+        })
+    }
+
+    pub fn sectors(&self) -> u16 {
+        self.sectors
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn into_storage(self) -> S {
+        self.storage
+    }
+
+    fn update_status(&self, machine: &mut Machine) {
+        let ready = (self.command.is_none() as i16) << 15;
+        machine.memory[DKSR] = ready | self.failed as i16;
+    }
+
+    fn transfer(
+        &mut self,
+        machine: &mut Machine,
+        command: u16,
+        privilege: PrivilegeMode,
+    ) -> io::Result<()> {
+        let sector = machine.memory[DKSN] as u16;
+        let buffer = machine.memory[DKBA] as u16;
+
+        if sector >= self.sectors {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        if privilege == PrivilegeMode::User
+            && (0..SECTOR_WORDS as u16)
+                .any(|i| machine.is_address_protected(buffer.wrapping_add(i)))
+        {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        self.storage
+            .seek(SeekFrom::Start(sector as u64 * SECTOR_BYTES))?;
+
+        let mut bytes = [0; SECTOR_WORDS * 2];
+        match command {
+            DISK_READ => {
+                self.storage.read_exact(&mut bytes)?;
+                for (i, word) in bytes.chunks_exact(2).enumerate() {
+                    let address = buffer.wrapping_add(i as u16);
+                    machine.memory[address] = i16::from_be_bytes([word[0], word[1]]);
+                }
+            }
+            DISK_WRITE => {
+                for (i, word) in bytes.chunks_exact_mut(2).enumerate() {
+                    let address = buffer.wrapping_add(i as u16);
+                    word.copy_from_slice(&machine.memory[address].to_be_bytes());
+                }
+                self.storage.write_all(&bytes)?;
+                self.storage.flush()?;
+            }
+            _ => return Err(io::ErrorKind::InvalidInput.into()),
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: Read + Write + Seek + Send + 'static> Device for BlockDevice<S> {
+    fn name(&self) -> &'static str {
+        "block"
+    }
+
+    fn addresses(&self) -> RangeInclusive<u16> {
+        DKSR..=DKBA
+    }
+
+    // meant for programs building their own file systems, DMA and all
+    fn user_accessible(&self) -> bool {
+        true
+    }
+
+    fn attach(&mut self, machine: &mut Machine) {
+        machine.memory[DKCR] = 0;
+        machine.memory[DKSN] = 0;
+        machine.memory[DKBA] = 0;
+        self.update_status(machine);
+    }
+
+    fn write(&mut self, machine: &mut Machine, address: u16, value: i16) {
+        match address {
+            DKCR if self.command.is_none() && matches!(value as u16, DISK_READ | DISK_WRITE) => {
+                self.command = Some((value as u16, machine.privilege));
+                machine.memory[DKSR] &= !(1 << 15); // clear 15th bit
+            }
+            DKSR => {
+                // the status is read only
+                self.update_status(machine);
+            }
+            _ => {}
+        }
+    }
+
+    fn ticks(&self) -> bool {
+        self.command.is_some()
+    }
+
+    fn tick(&mut self, machine: &mut Machine) {
+        if let Some((command, privilege)) = self.command.take() {
+            self.failed = self.transfer(machine, command, privilege).is_err();
+            self.update_status(machine);
+        }
+    }
+
+    // the error flag, then the command in progress and whether it came from supervisor mode
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.failed as u8];
+        if let Some((command, privilege)) = self.command {
+            state.extend(command.to_be_bytes());
+            state.push(privilege.is_supervisor() as u8);
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if let [failed, command @ ..] = state {
+            self.failed = *failed != 0;
+            self.command = match command {
+                [high, low, supervisor] => {
+                    let privilege = if *supervisor != 0 {
+                        PrivilegeMode::Supervisor
+                    } else {
+                        PrivilegeMode::User
+                    };
+                    Some((u16::from_be_bytes([*high, *low]), privilege))
+                }
+                _ => None,
+            };
+        }
+    }
+}
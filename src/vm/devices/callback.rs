@@ -0,0 +1,35 @@
+#![allow(deprecated)]
+
+use std::ops::RangeInclusive;
+
+use crate::vm::devices::Device;
+use crate::vm::machine::{Machine, MemoryModificationEvent};
+
+// A callback added with the deprecated `Machine::add_io_callback`, as a single address device.
+pub(crate) struct IoCallback {
+    pub(crate) address: u16,
+    pub(crate) callback: fn(&mut Machine, MemoryModificationEvent),
+}
+
+impl Device for IoCallback {
+    fn name(&self) -> &'static str {
+        "callback"
+    }
+
+    fn addresses(&self) -> RangeInclusive<u16> {
+        self.address..=self.address
+    }
+
+    fn ticks(&self) -> bool {
+        false
+    }
+
+    fn read(&mut self, machine: &mut Machine, _address: u16, value: i16) -> i16 {
+        (self.callback)(machine, MemoryModificationEvent::Read(value));
+        value
+    }
+
+    fn write(&mut self, machine: &mut Machine, _address: u16, value: i16) {
+        (self.callback)(machine, MemoryModificationEvent::Write(value));
+    }
+}
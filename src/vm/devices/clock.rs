@@ -0,0 +1,159 @@
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use crate::vm::devices::{CNTH, CNTL, DEFAULT_INSTRUCTIONS_PER_SECOND, Device, RTCM, RTCS};
+use crate::vm::machine::Machine;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClockMode {
+    // real time passed on the host
+    Wall,
+
+    // time derived from the instruction count only, so every run reads the same times
+    Virtual { instructions_per_second: u64 },
+}
+
+// Read only counter and clock registers.
+//
+// CNTL and CNTH are the low and high words of the 32-bit executed instruction count.
+// RTCS and RTCM are the seconds and milliseconds passed since the clock was attached.
+// Reading CNTL latches CNTH, and reading RTCS latches RTCM, so the low/seconds word has to be
+// read first for the pair to match.
+pub struct Clock {
+    mode: ClockMode,
+    started: Instant,
+    origin: u64,
+    count_high: u16,
+    milliseconds: u16,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new(ClockMode::Wall)
+    }
+}
+
+impl Clock {
+    pub fn new(mode: ClockMode) -> Self {
+        Self {
+            mode,
+            started: Instant::now(),
+            origin: 0,
+            count_high: 0,
+            milliseconds: 0,
+        }
+    }
+
+    pub fn virtual_clock() -> Self {
+        Self::new(ClockMode::Virtual {
+            instructions_per_second: DEFAULT_INSTRUCTIONS_PER_SECOND,
+        })
+    }
+
+    pub fn mode(&self) -> ClockMode {
+        self.mode
+    }
+
+    // time passed since the clock was attached
+    pub fn elapsed(&self, machine: &Machine) -> Duration {
+        match self.mode {
+            ClockMode::Wall => self.started.elapsed(),
+            ClockMode::Virtual {
+                instructions_per_second,
+            } => {
+                // stepping back to before the clock was attached reads as no time at all
+                let instructions = machine.instruction_count.saturating_sub(self.origin);
+                let nanos =
+                    instructions as u128 * 1_000_000_000 / instructions_per_second.max(1) as u128;
+                Duration::from_nanos(nanos as u64)
+            }
+        }
+    }
+
+    fn value(&self, address: u16) -> i16 {
+        if address == CNTH {
+            self.count_high as i16
+        } else {
+            self.milliseconds as i16
+        }
+    }
+}
+
+impl Device for Clock {
+    fn name(&self) -> &'static str {
+        "clock"
+    }
+
+    fn addresses(&self) -> RangeInclusive<u16> {
+        CNTL..=RTCM
+    }
+
+    fn ticks(&self) -> bool {
+        false
+    }
+
+    fn user_accessible(&self) -> bool {
+        true
+    }
+
+    fn attach(&mut self, machine: &mut Machine) {
+        self.started = Instant::now();
+        self.origin = machine.instruction_count;
+        self.count_high = 0;
+        self.milliseconds = 0;
+
+        for address in [CNTL, CNTH, RTCS, RTCM] {
+            machine.memory[address] = 0;
+        }
+    }
+
+    fn read(&mut self, machine: &mut Machine, address: u16, value: i16) -> i16 {
+        match address {
+            CNTL => {
+                let count = machine.instruction_count as u32;
+                self.count_high = (count >> 16) as u16;
+                machine.memory[CNTH] = self.count_high as i16;
+                machine.memory[CNTL] = count as i16;
+                count as i16
+            }
+            RTCS => {
+                let elapsed = self.elapsed(machine);
+                self.milliseconds = elapsed.subsec_millis() as u16;
+                machine.memory[RTCM] = self.milliseconds as i16;
+                machine.memory[RTCS] = elapsed.as_secs() as i16;
+                elapsed.as_secs() as i16
+            }
+            _ => value,
+        }
+    }
+
+    // the registers are read only, the latched words are put back and the others
+    // are recomputed on every read anyway
+    fn write(&mut self, machine: &mut Machine, address: u16, _value: i16) {
+        if address == CNTH || address == RTCM {
+            machine.memory[address] = self.value(address);
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.origin.to_be_bytes().to_vec();
+        state.extend((self.started.elapsed().as_millis() as u64).to_be_bytes());
+        state.extend(self.count_high.to_be_bytes());
+        state.extend(self.milliseconds.to_be_bytes());
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if state.len() == 20 {
+            let u64_at = |i: usize| u64::from_be_bytes(state[i..i + 8].try_into().unwrap());
+            let u16_at = |i: usize| u16::from_be_bytes([state[i], state[i + 1]]);
+
+            self.origin = u64_at(0);
+            self.started = Instant::now()
+                .checked_sub(Duration::from_millis(u64_at(8)))
+                .unwrap_or_else(Instant::now);
+            self.count_high = u16_at(16);
+            self.milliseconds = u16_at(18);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+use crate::vm::devices::{DDR, DSR, Device};
+use crate::vm::machine::Machine;
+
+pub const DISPLAY_INTERRUPT_VECTOR: u8 = 0x81;
+pub const DISPLAY_INTERRUPT_PRIORITY: u8 = 4;
+
+// characters the host may leave uncollected before the display stops accepting more
+pub const OUTPUT_CAPACITY: usize = 4096;
+
+// Display status (DSR) and data (DDR) registers.
+// A character written to DDR keeps the display busy until the end of the instruction,
+// then it is queued for the host, which collects it through `Machine::poll_display_data`.
+// While OUTPUT_CAPACITY characters are waiting the display stays busy, so a program that
+// outputs faster than the host collects waits for it like on real hardware.
+//
+// DSR bit 15 is the ready bit, bit 14 enables the display interrupt, which is requested while
+// the display is ready with interrupts enabled.
+pub struct Display {
+    vector: u8,
+    priority: u8,
+    output: VecDeque<u16>,
+    latched: Option<u16>,
+
+    // DSR isn't ready yet, the display sets it once it is
+    waiting: bool,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new(DISPLAY_INTERRUPT_VECTOR, DISPLAY_INTERRUPT_PRIORITY)
+    }
+}
+
+impl Display {
+    pub fn new(vector: u8, priority: u8) -> Self {
+        Self {
+            vector,
+            priority: priority & 0b111,
+            output: VecDeque::new(),
+            latched: None,
+            waiting: false,
+        }
+    }
+
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    pub fn take_output(&mut self) -> Option<u16> {
+        self.output.pop_front()
+    }
+
+    pub fn pending_output(&self) -> usize {
+        self.output.len()
+    }
+}
+
+impl Display {
+    fn is_ready(&self) -> bool {
+        self.latched.is_none() && self.output.len() < OUTPUT_CAPACITY
+    }
+
+    // the interrupt line is asserted while the display is ready and interrupts are enabled,
+    // a request the line no longer asserts is withdrawn
+    fn update_interrupt(&self, machine: &mut Machine) {
+        if machine.get_display_status() && machine.get_display_interrupt_enable_bit() {
+            machine.interrupt(self.vector, self.priority);
+        } else {
+            machine.cancel_interrupt(self.vector);
+        }
+    }
+}
+
+impl Device for Display {
+    fn name(&self) -> &'static str {
+        "display"
+    }
+
+    fn addresses(&self) -> RangeInclusive<u16> {
+        DSR..=DDR
+    }
+
+    // ready, interrupts disabled
+    fn attach(&mut self, machine: &mut Machine) {
+        machine.memory[DSR] = 1 << 15;
+        machine.memory[DDR] = 0;
+        self.waiting = false;
+    }
+
+    fn write(&mut self, machine: &mut Machine, address: u16, value: i16) {
+        if address == DDR {
+            machine.memory[DSR] &= !(1 << 15); // clear 15th bit
+            self.latched = Some(value as u16);
+            self.waiting = true;
+            self.update_interrupt(machine);
+        } else {
+            // the ready bit is read only
+            machine.set_display_status(self.is_ready());
+            self.waiting = !self.is_ready();
+            self.update_interrupt(machine);
+        }
+    }
+
+    fn ticks(&self) -> bool {
+        self.waiting
+    }
+
+    // also notices the host making room in a full queue
+    fn tick(&mut self, machine: &mut Machine) {
+        if let Some(data) = self.latched.take() {
+            self.output.push_back(data);
+        }
+
+        if self.is_ready() && !machine.get_display_status() {
+            machine.set_display_status(true);
+            self.update_interrupt(machine);
+        }
+        self.waiting = !machine.get_display_status();
+    }
+
+    // the restored DSR may be waiting for a character this display never latched
+    fn rewind(&mut self, machine: &mut Machine) {
+        self.waiting = !machine.get_display_status();
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.latched.is_some() as u8];
+        for data in self.latched.iter().chain(self.output.iter()) {
+            state.extend(data.to_be_bytes());
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let Some((latched, output)) = state.split_first() else {
+            return;
+        };
+
+        let mut data = output
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+
+        self.latched = if *latched != 0 { data.next() } else { None };
+        self.output = data.collect();
+    }
+}
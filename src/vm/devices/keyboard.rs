@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+use crate::vm::devices::{Device, KBDR, KBSR};
+use crate::vm::machine::Machine;
+
+pub const KEYBOARD_INTERRUPT_VECTOR: u8 = 0x80;
+pub const KEYBOARD_INTERRUPT_PRIORITY: u8 = 4;
+
+pub const DEFAULT_TYPE_AHEAD: usize = 256;
+
+// What happens to a key typed while the type-ahead buffer is full.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // the new key is lost
+    #[default]
+    DropNewest,
+
+    // the oldest waiting key makes room for it
+    DropOldest,
+}
+
+// Keyboard status (KBSR) and data (KBDR) registers.
+// Keys are supplied by the host through `Machine::set_keyboard_key` and wait in a type-ahead
+// buffer until the program has read the previous one from KBDR.
+pub struct Keyboard {
+    buffer: VecDeque<u16>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new(DEFAULT_TYPE_AHEAD, OverflowPolicy::default())
+    }
+}
+
+impl Keyboard {
+    // `capacity` doesn't count the key in KBDR
+    pub fn new(capacity: usize, overflow: OverflowPolicy) -> Self {
+        Self {
+            buffer: VecDeque::new(),
+            capacity,
+            overflow,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn overflow(&self) -> OverflowPolicy {
+        self.overflow
+    }
+
+    // keys already waiting stay, even if there are more than the new capacity
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    pub fn set_overflow(&mut self, overflow: OverflowPolicy) {
+        self.overflow = overflow;
+    }
+
+    // keys waiting behind the one in KBDR
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.buffer.len() >= self.capacity
+    }
+
+    // returns false if the key was dropped
+    pub fn push(&mut self, key: u16) -> bool {
+        if !self.is_full() {
+            self.buffer.push_back(key);
+            return true;
+        }
+
+        match self.overflow {
+            OverflowPolicy::DropNewest => false,
+            OverflowPolicy::DropOldest => {
+                if self.buffer.pop_front().is_none() {
+                    return false; // no room at all
+                }
+                self.buffer.push_back(key);
+                true
+            }
+        }
+    }
+
+    // queues keys regardless of the capacity
+    pub fn preload(&mut self, keys: impl IntoIterator<Item = u16>) {
+        self.buffer.extend(keys);
+    }
+
+    pub fn pop(&mut self) -> Option<u16> {
+        self.buffer.pop_front()
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+impl Device for Keyboard {
+    fn name(&self) -> &'static str {
+        "keyboard"
+    }
+
+    fn addresses(&self) -> RangeInclusive<u16> {
+        KBSR..=KBDR
+    }
+
+    // no key yet, interrupts enabled
+    fn attach(&mut self, machine: &mut Machine) {
+        machine.memory[KBSR] = 1 << 14;
+        machine.memory[KBDR] = 0;
+    }
+
+    fn read(&mut self, machine: &mut Machine, address: u16, value: i16) -> i16 {
+        // automatically reset status bit after a read
+        if address == KBDR {
+            machine.memory[KBSR] &= !(1 << 15); // clear 15th bit
+            machine.update_keyboard_interrupt();
+        }
+
+        value
+    }
+
+    // the program may have changed the enable bit
+    fn write(&mut self, machine: &mut Machine, address: u16, _value: i16) {
+        if address == KBSR {
+            machine.update_keyboard_interrupt();
+        }
+    }
+
+    fn ticks(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    // the next key arrives once the last one was read
+    fn tick(&mut self, machine: &mut Machine) {
+        if !machine.get_keyboard_status()
+            && let Some(key) = self.buffer.pop_front()
+        {
+            machine.deliver_key(key);
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.buffer
+            .iter()
+            .flat_map(|key| key.to_be_bytes())
+            .collect()
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        self.buffer = state
+            .chunks_exact(2)
+            .map(|key| u16::from_be_bytes([key[0], key[1]]))
+            .collect();
+    }
+}
@@ -0,0 +1,35 @@
+use std::ops::RangeInclusive;
+
+use crate::vm::devices::{Device, MCR};
+use crate::vm::machine::Machine;
+
+// Machine Control Register, the clock runs while bit 15 is set.
+#[derive(Default)]
+pub struct MachineControl;
+
+impl Device for MachineControl {
+    fn name(&self) -> &'static str {
+        "machine_control"
+    }
+
+    fn addresses(&self) -> RangeInclusive<u16> {
+        MCR..=MCR
+    }
+
+    fn ticks(&self) -> bool {
+        false
+    }
+
+    fn attach(&mut self, machine: &mut Machine) {
+        // set 15th bit to 1.
+        machine.set_memory_at_unchecked(MCR, 1 << 15);
+    }
+
+    fn write(&mut self, machine: &mut Machine, _address: u16, value: i16) {
+        if value >= 0 {
+            // 15th bit is cleared
+            // time to halt
+            machine.halted = true;
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::any::Any;
+use std::ops::RangeInclusive;
+
+use crate::vm::machine::Machine;
+
+pub mod block;
+pub(crate) mod callback;
+pub mod clock;
+pub mod display;
+pub mod keyboard;
+pub mod machine_control;
+pub mod protection;
+pub mod rng;
+pub mod sound;
+pub mod timer;
+pub mod video;
+
+pub use block::BlockDevice;
+pub use clock::{Clock, ClockMode};
+pub use display::Display;
+pub use keyboard::{Keyboard, OverflowPolicy};
+pub use machine_control::MachineControl;
+pub use protection::MemoryProtection;
+pub use rng::Rng;
+pub use sound::ToneGenerator;
+pub use timer::Timer;
+pub use video::Video;
+
+pub const KBSR: u16 = 0xFE00;
+pub const KBDR: u16 = 0xFE02;
+
+pub const DSR: u16 = 0xFE04;
+pub const DDR: u16 = 0xFE06;
+
+pub const TMR: u16 = 0xFE08;
+pub const TMI: u16 = 0xFE0A;
+
+pub const MPR: u16 = 0xFE12;
+
+pub const DKSR: u16 = 0xFE14;
+pub const DKCR: u16 = 0xFE16;
+pub const DKSN: u16 = 0xFE18;
+pub const DKBA: u16 = 0xFE1A;
+
+pub const SNDF: u16 = 0xFE20;
+pub const SNDD: u16 = 0xFE22;
+pub const SNDS: u16 = 0xFE24;
+
+pub const RNDR: u16 = 0xFE26;
+
+pub const CNTL: u16 = 0xFE28;
+pub const CNTH: u16 = 0xFE2A;
+pub const RTCS: u16 = 0xFE2C;
+pub const RTCM: u16 = 0xFE2E;
+
+pub const MCR: u16 = 0xFFFE;
+
+// how fast devices that turn instructions into time assume the machine runs
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u64 = 10_000;
+
+// A memory mapped device. Devices are attached to a `Machine` and get notified whenever
+// one of their addresses is accessed through `get_memory_at`/`set_memory_at`.
+// Their registers live in the machine's memory, so device state that software can see
+// should be kept there, anything else can be kept in the device itself.
+// Devices are `Send` so a machine can be moved to another thread.
+pub trait Device: Any + Send {
+    // the addresses this device responds to
+    fn addresses(&self) -> RangeInclusive<u16>;
+
+    // whether user programs may use the device's registers while device memory is protected
+    fn user_accessible(&self) -> bool {
+        false
+    }
+
+    // called once when the device is attached, useful for setting up initial register values.
+    fn attach(&mut self, _machine: &mut Machine) {}
+
+    // called when the machine reads `value` from `address`.
+    // the returned value is what the program sees.
+    fn read(&mut self, _machine: &mut Machine, _address: u16, value: i16) -> i16 {
+        value
+    }
+
+    // called after the machine writes `value` to `address`.
+    fn write(&mut self, _machine: &mut Machine, _address: u16, _value: i16) {}
+
+    // called once after every executed instruction.
+    // devices can raise interrupts from here with `Machine::interrupt`.
+    fn tick(&mut self, _machine: &mut Machine) {}
+
+    // whether `tick` has anything to do, idle devices aren't ticked.
+    // asked again after every call into the device and once it was handed out by
+    // `Machine::device_mut`, so a device goes idle and wakes up as its state changes.
+    fn ticks(&self) -> bool {
+        true
+    }
+
+    // called after `step_back` or `load_snapshot` moved the machine to another point in its run,
+    // the instruction count may now be lower than the device has seen.
+    fn rewind(&mut self, _machine: &mut Machine) {}
+
+    // identifies the device's state in snapshots, so it has to stay the same across builds
+    fn name(&self) -> &'static str;
+
+    // internal state that is not visible in memory, saved in snapshots and with every recorded
+    // step that changed it, so `step_back` can restore it
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_state(&mut self, _state: &[u8]) {}
+}
@@ -0,0 +1,34 @@
+use std::ops::RangeInclusive;
+
+use crate::vm::devices::{Device, MPR};
+use crate::vm::machine::Machine;
+
+// every block open to user programs
+pub const MPR_ALL_BLOCKS: u16 = 0xFFFF;
+
+// Memory Protection Register, as in the 3rd edition of the textbook.
+// Bit n guards the 4K block starting at n * x1000, user programs may only access the block
+// while its bit is set. Only the supervisor can access the MPR itself, even while device
+// memory is unprotected.
+// The MPR adds to the system and device memory protection of the machine, it starts with
+// every block open.
+#[derive(Default)]
+pub struct MemoryProtection;
+
+impl Device for MemoryProtection {
+    fn name(&self) -> &'static str {
+        "protection"
+    }
+
+    fn addresses(&self) -> RangeInclusive<u16> {
+        MPR..=MPR
+    }
+
+    fn ticks(&self) -> bool {
+        false
+    }
+
+    fn attach(&mut self, machine: &mut Machine) {
+        machine.set_memory_at_unchecked(MPR, MPR_ALL_BLOCKS as i16);
+    }
+}
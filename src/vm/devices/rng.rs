@@ -0,0 +1,73 @@
+use std::ops::RangeInclusive;
+
+use crate::bit_util::splitmix64;
+use crate::vm::devices::{Device, RNDR};
+use crate::vm::machine::Machine;
+
+// Random number register, every read of RNDR gives a new pseudo-random word.
+// The same seed always gives the same sequence.
+#[derive(Default)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+}
+
+impl Device for Rng {
+    fn name(&self) -> &'static str {
+        "rng"
+    }
+
+    fn addresses(&self) -> RangeInclusive<u16> {
+        RNDR..=RNDR
+    }
+
+    fn ticks(&self) -> bool {
+        false
+    }
+
+    fn user_accessible(&self) -> bool {
+        true
+    }
+
+    fn attach(&mut self, machine: &mut Machine) {
+        machine.memory[RNDR] = 0;
+    }
+
+    fn read(&mut self, machine: &mut Machine, _address: u16, _value: i16) -> i16 {
+        let value = splitmix64(&mut self.state) as i16;
+        machine.memory[RNDR] = value;
+        value
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.state.to_be_bytes().to_vec()
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if let Ok(state) = state.try_into() {
+            self.state = u64::from_be_bytes(state);
+        }
+    }
+}
+
+impl Machine {
+    // restarts the sequence of the attached `Rng`, returns false if there is none
+    pub fn seed_rng(&mut self, seed: u64) -> bool {
+        match self.device_mut::<Rng>() {
+            Some(rng) => {
+                rng.reseed(seed);
+                true
+            }
+            None => false,
+        }
+    }
+}
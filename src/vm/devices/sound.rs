@@ -0,0 +1,225 @@
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::vm::devices::{DEFAULT_INSTRUCTIONS_PER_SECOND, Device, SNDD, SNDF, SNDS};
+use crate::vm::machine::Machine;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+const AMPLITUDE: i16 = i16::MAX / 4;
+
+// A note the program played, timed in instructions since the device was attached.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tone {
+    pub start: u64,
+    pub duration: u64,
+    // 0 is a rest
+    pub frequency: u16,
+}
+
+// Square wave tone generator.
+//
+// SNDF holds the frequency in Hz. Writing a duration in instructions to SNDD plays the
+// frequency for that long, further writes are ignored until it's done.
+// SNDS bit 15 is set while the generator is ready for the next note.
+//
+// Notes are only recorded, `samples` renders them by mapping instructions to time.
+pub struct ToneGenerator {
+    instructions_per_second: u64,
+    origin: u64,
+    remaining: u64,
+    tones: Vec<Tone>,
+}
+
+impl Default for ToneGenerator {
+    fn default() -> Self {
+        Self::new(DEFAULT_INSTRUCTIONS_PER_SECOND)
+    }
+}
+
+impl ToneGenerator {
+    pub fn new(instructions_per_second: u64) -> Self {
+        Self {
+            instructions_per_second: instructions_per_second.max(1),
+            origin: 0,
+            remaining: 0,
+            tones: Vec::new(),
+        }
+    }
+
+    pub fn tones(&self) -> &[Tone] {
+        &self.tones
+    }
+
+    fn sample_at(&self, instructions: u64) -> u64 {
+        let sample =
+            instructions as u128 * SAMPLE_RATE as u128 / self.instructions_per_second as u128;
+        u64::try_from(sample).unwrap_or(u64::MAX)
+    }
+
+    // samples from attaching the device to the end of the last note
+    pub fn sample_count(&self) -> u64 {
+        self.tones
+            .iter()
+            .map(|tone| self.sample_at(tone.start.saturating_add(tone.duration)))
+            .max()
+            .unwrap_or(0)
+    }
+
+    // notes never overlap, so they render one after the other with silence in between
+    fn render(&self) -> impl Iterator<Item = i16> + '_ {
+        let mut position = 0;
+        self.tones.iter().flat_map(move |tone| {
+            let start = self.sample_at(tone.start).max(position);
+            let end = self
+                .sample_at(tone.start.saturating_add(tone.duration))
+                .max(start);
+            let silence = position..start;
+            let frequency = tone.frequency;
+            position = end;
+
+            silence
+                .map(|_| 0)
+                .chain((0..end - start).map(move |i| square_wave(frequency, i)))
+        })
+    }
+
+    // 16-bit mono at SAMPLE_RATE, from attaching the device to the end of the last note.
+    // the whole render is held in memory, `write_wav` streams it instead
+    pub fn samples(&self) -> Vec<i16> {
+        self.render().collect()
+    }
+
+    pub fn write_wav(&self, mut writer: impl Write) -> io::Result<()> {
+        // the RIFF sizes are 32 bits and include the 36 header bytes after them
+        let data_len = u32::try_from(self.sample_count())
+            .ok()
+            .and_then(|count| count.checked_mul(2))
+            .filter(|&len| len <= u32::MAX - 36)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "too many samples for a WAV file",
+                )
+            })?;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(36 + data_len).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        // PCM, mono, 16 bits per sample
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&data_len.to_le_bytes())?;
+        for sample in self.render() {
+            writer.write_all(&sample.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+// sample `i` of a note, counted from its start
+fn square_wave(frequency: u16, i: u64) -> i16 {
+    if frequency == 0 {
+        return 0;
+    }
+
+    // which half of the period the sample falls in
+    let half = i * frequency as u64 * 2 / SAMPLE_RATE as u64;
+    if half.is_multiple_of(2) {
+        AMPLITUDE
+    } else {
+        -AMPLITUDE
+    }
+}
+
+impl Device for ToneGenerator {
+    fn name(&self) -> &'static str {
+        "sound"
+    }
+
+    fn addresses(&self) -> RangeInclusive<u16> {
+        SNDF..=SNDS
+    }
+
+    fn user_accessible(&self) -> bool {
+        true
+    }
+
+    fn attach(&mut self, machine: &mut Machine) {
+        self.origin = machine.instruction_count;
+        machine.memory[SNDF] = 0;
+        machine.memory[SNDD] = 0;
+        machine.memory[SNDS] = 1 << 15;
+    }
+
+    fn write(&mut self, machine: &mut Machine, address: u16, value: i16) {
+        match address {
+            SNDD if self.remaining == 0 && value != 0 => {
+                self.remaining = value as u16 as u64;
+                self.tones.push(Tone {
+                    start: machine.instruction_count.saturating_sub(self.origin),
+                    duration: self.remaining,
+                    frequency: machine.memory[SNDF] as u16,
+                });
+                machine.memory[SNDS] = 0;
+            }
+            SNDS => {
+                // the status is read only
+                machine.memory[SNDS] = ((self.remaining == 0) as i16) << 15;
+            }
+            _ => {}
+        }
+    }
+
+    fn ticks(&self) -> bool {
+        self.remaining > 0
+    }
+
+    fn tick(&mut self, machine: &mut Machine) {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                machine.memory[SNDS] = 1 << 15;
+            }
+        }
+    }
+
+    // notes from the undone part of the run were never played
+    fn rewind(&mut self, machine: &mut Machine) {
+        let now = machine.instruction_count.saturating_sub(self.origin);
+        self.tones.retain(|tone| tone.start < now);
+
+        // the tick of the instruction that started a note already counted down once
+        self.remaining = self
+            .tones
+            .last()
+            .map(|tone| (tone.start + tone.duration).saturating_sub(now + 1))
+            .unwrap_or(0);
+    }
+
+    // the note in progress is all that matters to the program
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.origin.to_be_bytes().to_vec();
+        state.extend(self.remaining.to_be_bytes());
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if let Some((origin, remaining)) = state.split_first_chunk::<8>()
+            && let Ok(remaining) = <[u8; 8]>::try_from(remaining)
+        {
+            self.origin = u64::from_be_bytes(*origin);
+            self.remaining = u64::from_be_bytes(remaining);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::ops::RangeInclusive;
+
+use crate::vm::devices::{Device, TMI, TMR};
+use crate::vm::machine::Machine;
+
+pub const TIMER_INTERRUPT_VECTOR: u8 = 0x82;
+pub const TIMER_INTERRUPT_PRIORITY: u8 = 5;
+
+// Programmable interval timer, counting executed instructions.
+//
+// TMI holds the interval, writing it restarts the countdown and 0 stops the timer.
+// TMR is the control/status register:
+//   bit 15 is set every time the interval elapses, and cleared when TMR is read
+//   bit 14 enables the timer interrupt
+pub struct Timer {
+    vector: u8,
+    priority: u8,
+    remaining: u16,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new(TIMER_INTERRUPT_VECTOR, TIMER_INTERRUPT_PRIORITY)
+    }
+}
+
+impl Timer {
+    pub fn new(vector: u8, priority: u8) -> Self {
+        Self {
+            vector,
+            priority: priority & 0b111,
+            remaining: 0,
+        }
+    }
+
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    // instructions left until the interval elapses
+    pub fn remaining(&self) -> u16 {
+        self.remaining
+    }
+
+    // the interrupt line is asserted while an elapsed interval is unread with interrupts enabled,
+    // a request the line no longer asserts is withdrawn
+    fn update_interrupt(&self, machine: &mut Machine) {
+        let status = machine.memory[TMR] as u16;
+        if status & (1 << 15) != 0 && status & (1 << 14) != 0 {
+            machine.interrupt(self.vector, self.priority);
+        } else {
+            machine.cancel_interrupt(self.vector);
+        }
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &'static str {
+        "timer"
+    }
+
+    fn addresses(&self) -> RangeInclusive<u16> {
+        TMR..=TMI
+    }
+
+    // stopped, with the interrupt disabled, whatever the memory held before
+    fn attach(&mut self, machine: &mut Machine) {
+        machine.memory[TMR] = 0;
+        machine.memory[TMI] = 0;
+        self.remaining = 0;
+    }
+
+    fn read(&mut self, machine: &mut Machine, address: u16, value: i16) -> i16 {
+        if address == TMR {
+            machine.memory[TMR] &= !(1 << 15); // clear 15th bit
+            self.update_interrupt(machine);
+        }
+
+        value
+    }
+
+    fn write(&mut self, machine: &mut Machine, address: u16, value: i16) {
+        if address == TMI {
+            self.remaining = value as u16;
+        } else {
+            self.update_interrupt(machine);
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.remaining.to_be_bytes().to_vec()
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if let [high, low] = state {
+            self.remaining = u16::from_be_bytes([*high, *low]);
+        }
+    }
+
+    fn tick(&mut self, machine: &mut Machine) {
+        let interval = machine.memory[TMI] as u16;
+        if interval == 0 {
+            return;
+        }
+
+        if self.remaining == 0 {
+            self.remaining = interval;
+        }
+
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            return;
+        }
+
+        self.remaining = interval;
+        machine.memory[TMR] |= 1 << 15; // 15th bit is set.
+        self.update_interrupt(machine);
+    }
+}
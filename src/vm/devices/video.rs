@@ -0,0 +1,171 @@
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::vm::devices::Device;
+use crate::vm::machine::Machine;
+
+// same layout as PennSim's display
+pub const VIDEO_START: u16 = 0xC000;
+pub const VIDEO_WIDTH: usize = 128;
+pub const VIDEO_HEIGHT: usize = 124;
+pub const VIDEO_END: u16 = VIDEO_START + (VIDEO_WIDTH * VIDEO_HEIGHT) as u16 - 1;
+
+// Video memory, one word per pixel in rows from the top left.
+// Pixels are 15-bit RGB, 5 bits each for red (bits 14-10), green (9-5) and blue (4-0).
+// The pixels are plain memory the device doesn't claim, attaching it only lets `Machine::frame`
+// read them. Pixels nothing has written yet are black.
+#[derive(Default)]
+pub struct Video;
+
+impl Device for Video {
+    fn name(&self) -> &'static str {
+        "video"
+    }
+
+    #[allow(clippy::reversed_empty_ranges)]
+    fn addresses(&self) -> RangeInclusive<u16> {
+        1..=0
+    }
+
+    fn ticks(&self) -> bool {
+        false
+    }
+}
+
+// A copy of the framebuffer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pixels: Vec<u16>,
+}
+
+impl Frame {
+    pub fn width(&self) -> usize {
+        VIDEO_WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        VIDEO_HEIGHT
+    }
+
+    // the raw 15-bit pixel
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * VIDEO_WIDTH + x] & 0x7FFF
+    }
+
+    // the pixel with each channel scaled up to 8 bits
+    pub fn rgb(&self, x: usize, y: usize) -> [u8; 3] {
+        let pixel = self.pixel(x, y);
+        let scale = |channel: u16| ((channel << 3) | (channel >> 2)) as u8;
+
+        [
+            scale((pixel >> 10) & 0x1F),
+            scale((pixel >> 5) & 0x1F),
+            scale(pixel & 0x1F),
+        ]
+    }
+
+    // 8-bit RGB rows, top to bottom
+    pub fn to_rgb(&self) -> Vec<u8> {
+        (0..VIDEO_HEIGHT)
+            .flat_map(|y| (0..VIDEO_WIDTH).flat_map(move |x| self.rgb(x, y)))
+            .collect()
+    }
+
+    // binary PPM (P6)
+    pub fn write_ppm(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "P6\n{VIDEO_WIDTH} {VIDEO_HEIGHT}\n255\n")?;
+        writer.write_all(&self.to_rgb())
+    }
+
+    // uncompressed RGB PNG
+    pub fn write_png(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let mut header = Vec::new();
+        header.extend((VIDEO_WIDTH as u32).to_be_bytes());
+        header.extend((VIDEO_HEIGHT as u32).to_be_bytes());
+        // 8 bits per channel, RGB, default compression, filtering and no interlacing
+        header.extend([8, 2, 0, 0, 0]);
+        write_png_chunk(&mut writer, b"IHDR", &header)?;
+
+        // every row starts with its filter type, 0 is none
+        let rgb = self.to_rgb();
+        let mut rows = Vec::with_capacity(rgb.len() + VIDEO_HEIGHT);
+        for row in rgb.chunks_exact(VIDEO_WIDTH * 3) {
+            rows.push(0);
+            rows.extend(row);
+        }
+        write_png_chunk(&mut writer, b"IDAT", &zlib_stored(&rows))?;
+
+        write_png_chunk(&mut writer, b"IEND", &[])
+    }
+}
+
+impl Machine {
+    // None unless a `Video` device is attached
+    pub fn frame(&self) -> Option<Frame> {
+        self.device::<Video>()?;
+
+        let pixels = (VIDEO_START..=VIDEO_END)
+            .map(|address| {
+                if self.memory.is_touched(address) {
+                    self.memory[address] as u16
+                } else {
+                    0
+                }
+            })
+            .collect();
+        Some(Frame { pixels })
+    }
+}
+
+fn write_png_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let crc = crc32(kind.iter().chain(data));
+    writer.write_all(&crc.to_be_bytes())
+}
+
+// a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = u16::MAX as usize;
+
+    let mut out = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        out.push(last as u8);
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    let mut crc = u32::MAX;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
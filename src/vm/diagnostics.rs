@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::vm::instructions::{Instruction, Register};
+use crate::vm::machine::{Machine, PrivilegeMode};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UninitializedRead {
+    Register(Register),
+    Memory(u16),
+}
+
+// a read of something that was never written, reported once per register or address
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub pc: u16,
+    pub read: UninitializedRead,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.read {
+            UninitializedRead::Register(register) => write!(
+                f,
+                "x{:04X}: read of uninitialized register R{}",
+                self.pc, register as u8
+            ),
+            UninitializedRead::Memory(address) => write!(
+                f,
+                "x{:04X}: read of uninitialized memory at x{address:04X}",
+                self.pc
+            ),
+        }
+    }
+}
+
+// Memory counts as initialized once it is touched, so loaded programs and the OS are, while
+// `????` words and everything else are not. Registers count once they are written, see
+// `Registers::is_initialized`.
+pub struct InitChecker {
+    reported_registers: u8,
+    reported_memory: HashSet<u16>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl InitChecker {
+    fn new() -> Self {
+        Self {
+            reported_registers: 0,
+            reported_memory: HashSet::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+}
+
+impl Machine {
+    pub fn enable_init_checks(&mut self) {
+        self.init_checker = Some(InitChecker::new());
+    }
+
+    pub fn disable_init_checks(&mut self) {
+        self.init_checker = None;
+    }
+
+    // after the whole state was replaced, what was reported before says nothing about it
+    pub(crate) fn reset_init_checks(&mut self) {
+        if self.init_checker.is_some() {
+            self.init_checker = Some(InitChecker::new());
+        }
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        self.init_checker
+            .as_ref()
+            .map(|checker| checker.diagnostics.as_slice())
+            .unwrap_or_default()
+    }
+
+    // the diagnostics reported since the last call
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.init_checker
+            .as_mut()
+            .map(|checker| std::mem::take(&mut checker.diagnostics))
+            .unwrap_or_default()
+    }
+
+    // only the program's own reads are checked, the OS saves and restores registers it never
+    // looks at
+    pub(crate) fn check_register_reads(&mut self, instr: Instruction) {
+        let pc = self.executing_pc;
+        let user = self.privilege == PrivilegeMode::User;
+        let registers = &self.registers;
+        let Some(checker) = &mut self.init_checker else {
+            return;
+        };
+
+        // AND with 0 is the usual way to clear a register, its value doesn't matter
+        let sources = match instr {
+            Instruction::AndImmediate(_, _, imm5) if imm5.into_inner() == 0 => vec![],
+            _ => instr.source_registers(),
+        };
+
+        if user {
+            for register in sources {
+                let bit = 1 << register as u8;
+                if !registers.is_initialized(register) && checker.reported_registers & bit == 0 {
+                    checker.reported_registers |= bit;
+                    checker.diagnostics.push(Diagnostic {
+                        pc,
+                        read: UninitializedRead::Register(register),
+                    });
+                }
+            }
+        }
+    }
+
+    pub(crate) fn check_memory_read(&mut self, address: u16) {
+        if self.memory.is_touched(address) || self.is_address_mapped_to_device(address) {
+            return;
+        }
+
+        let pc = self.executing_pc;
+        let Some(checker) = &mut self.init_checker else {
+            return;
+        };
+
+        if checker.reported_memory.insert(address) {
+            checker.diagnostics.push(Diagnostic {
+                pc,
+                read: UninitializedRead::Memory(address),
+            });
+        }
+    }
+}
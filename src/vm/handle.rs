@@ -0,0 +1,293 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::JoinHandle;
+
+use crate::vm::console::Console;
+use crate::vm::debugger::{StopReason, WatchpointHit};
+use crate::vm::instructions::Registers;
+use crate::vm::machine::Machine;
+
+// instructions run between looking for new commands.
+// smaller than the display's OUTPUT_CAPACITY, which is only emptied between slices
+const SLICE: u64 = 1024;
+
+type Inspection = Box<dyn FnOnce(&mut Machine) + Send>;
+
+enum Command {
+    Pause,
+    Resume,
+    Step,
+    InjectKey(u16),
+    SetBreakpoint(u16),
+    RemoveBreakpoint(u16),
+    Inspect(Inspection),
+    Shutdown,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopCause {
+    Paused,
+    Stepped,
+    Breakpoint,
+    Watchpoint(WatchpointHit),
+    // exception vector, only reported while `break_on_exceptions` is set
+    Exception(u8),
+    // the machine's `instruction_limit` or `deadline`, they stay set
+    InstructionLimit,
+    Deadline,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    // a character written to the display
+    Output(u16),
+    Halted,
+
+    // the machine is no longer running, `pc` is the next instruction
+    Stopped { pc: u16, cause: StopCause },
+}
+
+// Owns a machine running on a worker thread. The machine starts out paused, so breakpoints
+// can be set before calling `resume`. Dropping the handle stops the thread.
+pub struct MachineHandle {
+    commands: Sender<Command>,
+    events: Receiver<Event>,
+    thread: Option<JoinHandle<Machine>>,
+}
+
+impl MachineHandle {
+    pub fn spawn(machine: Machine) -> Self {
+        let (commands, command_receiver) = mpsc::channel();
+        let (event_sender, events) = mpsc::channel();
+
+        let thread = std::thread::spawn(move || work(machine, command_receiver, event_sender));
+
+        Self {
+            commands,
+            events,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn events(&self) -> &Receiver<Event> {
+        &self.events
+    }
+
+    pub fn pause(&self) {
+        self.send(Command::Pause);
+    }
+
+    // a breakpoint at the current instruction is stepped over.
+    // like `step`, sends `Event::Halted` again if the machine already halted
+    pub fn resume(&self) {
+        self.send(Command::Resume);
+    }
+
+    // executes a single instruction, pausing the machine if it was running
+    pub fn step(&self) {
+        self.send(Command::Step);
+    }
+
+    // keys wait in line until the keyboard is empty
+    pub fn inject_key(&self, key: u16) {
+        self.send(Command::InjectKey(key));
+    }
+
+    pub fn set_breakpoint(&self, address: u16) {
+        self.send(Command::SetBreakpoint(address));
+    }
+
+    pub fn remove_breakpoint(&self, address: u16) {
+        self.send(Command::RemoveBreakpoint(address));
+    }
+
+    // runs `f` on the worker thread between two instructions and waits for the result
+    pub fn inspect<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Machine) -> R + Send + 'static,
+    ) -> R {
+        let (sender, receiver) = mpsc::channel();
+        self.send(Command::Inspect(Box::new(move |machine| {
+            let _ = sender.send(f(machine));
+        })));
+
+        receiver.recv().expect("machine thread stopped")
+    }
+
+    // the registers along with the PC
+    pub fn registers(&self) -> (Registers, u16) {
+        self.inspect(|machine| (machine.registers.clone(), machine.ip))
+    }
+
+    pub fn read_memory(&self, address: u16, len: u16) -> Vec<i16> {
+        self.inspect(move |machine| {
+            (0..len)
+                .map(|i| machine.memory[address.wrapping_add(i)])
+                .collect()
+        })
+    }
+
+    // stops the worker thread and gives the machine back
+    pub fn join(mut self) -> Machine {
+        self.send(Command::Shutdown);
+        self.thread
+            .take()
+            .expect("machine thread already joined")
+            .join()
+            .expect("machine thread panicked")
+    }
+
+    fn send(&self, command: Command) {
+        self.commands.send(command).expect("machine thread stopped");
+    }
+}
+
+impl Drop for MachineHandle {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.commands.send(Command::Shutdown);
+            let _ = thread.join();
+        }
+    }
+}
+
+// feeds injected keys to the keyboard and sends the display's output back as events
+struct ChannelConsole {
+    keys: VecDeque<u16>,
+    events: Sender<Event>,
+}
+
+impl Console for ChannelConsole {
+    fn read_key(&mut self) -> io::Result<Option<u16>> {
+        Ok(self.keys.pop_front())
+    }
+
+    fn write_char(&mut self, data: u16) -> io::Result<()> {
+        // nobody listening isn't a reason to stop the machine
+        let _ = self.events.send(Event::Output(data));
+        Ok(())
+    }
+}
+
+fn work(mut machine: Machine, commands: Receiver<Command>, events: Sender<Event>) -> Machine {
+    let mut console = ChannelConsole {
+        keys: VecDeque::new(),
+        events: events.clone(),
+    };
+
+    let mut running = false;
+    // set on resume, so a breakpoint at the current instruction doesn't stop it again
+    let mut resuming = false;
+
+    loop {
+        let command = if running {
+            match commands.try_recv() {
+                Ok(command) => Some(command),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match commands.recv() {
+                Ok(command) => Some(command),
+                Err(_) => break,
+            }
+        };
+
+        if let Some(command) = command {
+            match command {
+                Command::Pause => {
+                    if running {
+                        running = false;
+                        stopped(&machine, &events, StopCause::Paused);
+                    }
+                }
+                // a halted machine answers both with another `Event::Halted`
+                Command::Resume => {
+                    running = true;
+                    resuming = true;
+                }
+                Command::Step => {
+                    running = false;
+                    let start = machine.instruction_count;
+                    let reason = run_slice(&mut machine, &mut console, true, |machine| {
+                        machine.instruction_count > start
+                    });
+                    report(&machine, &events, reason, Some(StopCause::Stepped));
+                }
+                Command::InjectKey(key) => console.keys.push_back(key),
+                Command::SetBreakpoint(address) => machine.set_breakpoint(address),
+                Command::RemoveBreakpoint(address) => {
+                    machine.remove_breakpoint(address);
+                }
+                Command::Inspect(f) => f(&mut machine),
+                Command::Shutdown => break,
+            }
+
+            // take every waiting command before running on
+            continue;
+        }
+
+        let end = machine.instruction_count.saturating_add(SLICE);
+        let reason = run_slice(&mut machine, &mut console, resuming, |machine| {
+            machine.instruction_count >= end
+        });
+        resuming = false;
+        running = !report(&machine, &events, reason, None);
+    }
+
+    machine
+}
+
+// `Machine::continue_until` with the injected keys delivered before and the output passed on after
+fn run_slice(
+    machine: &mut Machine,
+    console: &mut ChannelConsole,
+    step_over_breakpoint: bool,
+    predicate: impl FnMut(&Machine) -> bool,
+) -> StopReason {
+    while machine.keyboard_has_room()
+        && let Some(key) = console.keys.pop_front()
+    {
+        machine.set_keyboard_key(key);
+    }
+
+    let reason = machine.continue_until(step_over_breakpoint, predicate);
+    machine.flush_console(console).unwrap();
+    reason
+}
+
+// sends the event for why `run_slice` returned, `finished` is the cause to report when its
+// predicate held. returns whether the machine stopped
+fn report(
+    machine: &Machine,
+    events: &Sender<Event>,
+    reason: StopReason,
+    finished: Option<StopCause>,
+) -> bool {
+    let cause = match reason {
+        StopReason::Halted => {
+            let _ = events.send(Event::Halted);
+            return true;
+        }
+        StopReason::Predicate => match finished {
+            Some(cause) => cause,
+            None => return false,
+        },
+        StopReason::Breakpoint(_) => StopCause::Breakpoint,
+        StopReason::Watchpoint(hit) => StopCause::Watchpoint(hit),
+        StopReason::Exception(vector) => StopCause::Exception(vector),
+        StopReason::InstructionLimit => StopCause::InstructionLimit,
+        StopReason::Deadline => StopCause::Deadline,
+    };
+
+    stopped(machine, events, cause);
+    true
+}
+
+fn stopped(machine: &Machine, events: &Sender<Event>, cause: StopCause) {
+    let _ = events.send(Event::Stopped {
+        pc: machine.ip,
+        cause,
+    });
+}
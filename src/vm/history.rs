@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+
+use crate::vm::instructions::Registers;
+use crate::vm::interrupts::InterruptRequest;
+use crate::vm::machine::{ConditionCode, Machine, PrivilegeMode};
+use crate::vm::memory::JournalEntry;
+
+// Everything a single step can change in the CPU.
+struct CpuState {
+    registers: Registers,
+    ip: u16,
+    condition_code: ConditionCode,
+    privilege: PrivilegeMode,
+    priority: u8,
+    halted: bool,
+    instruction_count: u64,
+    pending_interrupts: Vec<InterruptRequest>,
+}
+
+impl CpuState {
+    fn capture(machine: &Machine) -> Self {
+        Self {
+            registers: machine.registers.clone(),
+            ip: machine.ip,
+            condition_code: machine.condition_code,
+            privilege: machine.privilege,
+            priority: machine.priority,
+            halted: machine.halted,
+            instruction_count: machine.instruction_count,
+            pending_interrupts: machine.pending_interrupts.clone(),
+        }
+    }
+
+    fn restore(self, machine: &mut Machine) {
+        machine.registers = self.registers;
+        machine.ip = self.ip;
+        machine.condition_code = self.condition_code;
+        machine.privilege = self.privilege;
+        machine.priority = self.priority;
+        machine.halted = self.halted;
+        machine.instruction_count = self.instruction_count;
+        machine.pending_interrupts = self.pending_interrupts;
+    }
+}
+
+struct StepRecord {
+    before: CpuState,
+    writes: Vec<JournalEntry>,
+    // `save_state` from before the step, of the devices (by index) the step changed
+    devices: Vec<(usize, Vec<u8>)>,
+}
+
+// Ring buffer of the most recent steps, the oldest step is forgotten once `capacity` is reached.
+pub struct History {
+    records: VecDeque<StepRecord>,
+    capacity: usize,
+}
+
+impl History {
+    fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity.min(4096)),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}
+
+// Reverse execution. Registers, PSR, PC and memory (including device registers) are journaled,
+// as is the internal state of devices (e.g. a timer's countdown) through `Device::save_state`.
+impl Machine {
+    // starts recording up to `capacity` steps, clearing any existing history
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(History::new(capacity));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    pub(crate) fn step_recorded(&mut self) {
+        let before = CpuState::capture(self);
+        let device_states: Vec<Vec<u8>> = self.devices.iter().map(|d| d.save_state()).collect();
+
+        self.memory.start_journal();
+        self.execute_step();
+        let writes = self.memory.take_journal();
+
+        let devices = device_states
+            .into_iter()
+            .enumerate()
+            .filter(|(index, state)| self.devices[*index].save_state() != *state)
+            .collect();
+
+        let Some(history) = &mut self.history else {
+            return;
+        };
+
+        if history.capacity == 0 {
+            return;
+        }
+
+        if history.records.len() >= history.capacity {
+            history.records.pop_front();
+        }
+
+        history.records.push_back(StepRecord {
+            before,
+            writes,
+            devices,
+        });
+    }
+
+    // undoes up to `n` steps, returning how many were actually undone
+    pub fn step_back(&mut self, n: usize) -> usize {
+        let mut undone = 0;
+        while undone < n {
+            let Some(record) = self.history.as_mut().and_then(|h| h.records.pop_back()) else {
+                break;
+            };
+
+            self.memory.undo(&record.writes);
+            record.before.restore(self);
+            for (index, state) in record.devices {
+                self.devices[index].load_state(&state);
+            }
+            undone += 1;
+        }
+
+        if undone > 0 {
+            self.rewind_devices();
+        }
+
+        undone
+    }
+
+    // steps back until the instruction at `address` is about to execute again.
+    // returns false (leaving the machine at the oldest recorded state) if it is not in the history.
+    pub fn run_back_to(&mut self, address: u16) -> bool {
+        while self.step_back(1) == 1 {
+            if self.ip == address {
+                return true;
+            }
+        }
+
+        false
+    }
+}
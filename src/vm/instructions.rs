@@ -5,6 +5,7 @@ use crate::vm::instructions::Instruction::{
     Store, StoreIndirect, StoreRegister, Trap,
 };
 use crate::vm::machine::PrivilegeMode;
+use std::fmt;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Register {
@@ -526,13 +527,126 @@ impl Instruction {
     }
 }
 
-#[derive(Debug)]
+// disassembly, offsets are printed relative to the next instruction like in the encoding
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = |register: Register| register as u8;
+
+        match *self {
+            Add(dest, s1, s2) => write!(f, "ADD R{}, R{}, R{}", r(dest), r(s1), r(s2)),
+            AddImmediate(dest, s1, imm5) => {
+                write!(f, "ADD R{}, R{}, #{}", r(dest), r(s1), imm5.into_inner())
+            }
+            And(dest, s1, s2) => write!(f, "AND R{}, R{}, R{}", r(dest), r(s1), r(s2)),
+            AndImmediate(dest, s1, imm5) => {
+                write!(f, "AND R{}, R{}, #{}", r(dest), r(s1), imm5.into_inner())
+            }
+            Branch(flags, offset) if flags.into_flags() == 0 => {
+                write!(f, "NOP #{}", offset.into_inner())
+            }
+            Branch(flags, offset) => {
+                write!(f, "BR")?;
+                for (set, name) in [
+                    (flags.negative, "n"),
+                    (flags.zero, "z"),
+                    (flags.positive, "p"),
+                ] {
+                    if set {
+                        write!(f, "{name}")?;
+                    }
+                }
+                write!(f, " #{}", offset.into_inner())
+            }
+            Jump(Register::R7) => write!(f, "RET"),
+            Jump(baser) => write!(f, "JMP R{}", r(baser)),
+            JumpSubroutine(offset) => write!(f, "JSR #{}", offset.into_inner()),
+            JumpSubroutineRegister(baser) => write!(f, "JSRR R{}", r(baser)),
+            Load(dest, offset) => write!(f, "LD R{}, #{}", r(dest), offset.into_inner()),
+            LoadIndirect(dest, offset) => write!(f, "LDI R{}, #{}", r(dest), offset.into_inner()),
+            LoadRegister(dest, baser, offset) => write!(
+                f,
+                "LDR R{}, R{}, #{}",
+                r(dest),
+                r(baser),
+                offset.into_inner()
+            ),
+            LoadEffectiveAddress(dest, offset) => {
+                write!(f, "LEA R{}, #{}", r(dest), offset.into_inner())
+            }
+            Not(dest, source) => write!(f, "NOT R{}, R{}", r(dest), r(source)),
+            ReturnFromInterrupt => write!(f, "RTI"),
+            Store(source, offset) => write!(f, "ST R{}, #{}", r(source), offset.into_inner()),
+            StoreIndirect(source, offset) => {
+                write!(f, "STI R{}, #{}", r(source), offset.into_inner())
+            }
+            StoreRegister(source, baser, offset) => write!(
+                f,
+                "STR R{}, R{}, #{}",
+                r(source),
+                r(baser),
+                offset.into_inner()
+            ),
+            Trap(0x20) => write!(f, "GETC"),
+            Trap(0x21) => write!(f, "OUT"),
+            Trap(0x22) => write!(f, "PUTS"),
+            Trap(0x23) => write!(f, "IN"),
+            Trap(0x24) => write!(f, "PUTSP"),
+            Trap(0x25) => write!(f, "HALT"),
+            Trap(vector) => write!(f, "TRAP x{vector:02X}"),
+            Reserved => write!(f, "RESERVED"),
+        }
+    }
+}
+
+impl Instruction {
+    // registers whose values the instruction uses
+    pub fn source_registers(self) -> Vec<Register> {
+        match self {
+            Add(_, s1, s2) | And(_, s1, s2) => vec![s1, s2],
+            AddImmediate(_, s1, _) | AndImmediate(_, s1, _) | Not(_, s1) => vec![s1],
+            Jump(baser) | JumpSubroutineRegister(baser) | LoadRegister(_, baser, _) => vec![baser],
+            Store(source, _) | StoreIndirect(source, _) => vec![source],
+            StoreRegister(source, baser, _) => vec![source, baser],
+            Branch(..)
+            | JumpSubroutine(_)
+            | Load(..)
+            | LoadIndirect(..)
+            | LoadEffectiveAddress(..)
+            | ReturnFromInterrupt
+            | Trap(_)
+            | Reserved => vec![],
+        }
+    }
+
+    // the register the instruction writes, if any. R6 changing through the stack isn't counted
+    pub fn destination_register(self) -> Option<Register> {
+        match self {
+            Add(dest, ..)
+            | AddImmediate(dest, ..)
+            | And(dest, ..)
+            | AndImmediate(dest, ..)
+            | Load(dest, _)
+            | LoadIndirect(dest, _)
+            | LoadRegister(dest, ..)
+            | LoadEffectiveAddress(dest, _)
+            | Not(dest, _) => Some(dest),
+            JumpSubroutine(_) | JumpSubroutineRegister(_) => Some(Register::R7),
+            Branch(..) | Jump(_) | ReturnFromInterrupt | Store(..) | StoreIndirect(..)
+            | StoreRegister(..) | Trap(_) | Reserved => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Registers {
     reg: [i16; 8],
 
     ssp: i16, // R6 is actually an alias to one of these.
     usp: i16,
     pub mode: PrivilegeMode, // kinda duplication
+
+    // bit per register, set once anything wrote it through `get_mut`
+    initialized: u8,
 }
 
 impl Default for Registers {
@@ -544,6 +658,9 @@ impl Default for Registers {
             usp: (0xFE00u16) as i16,
 
             mode: PrivilegeMode::default(),
+
+            // the stack pointer is set up by the machine
+            initialized: 1 << Register::R6 as u8,
         }
     }
 }
@@ -579,7 +696,31 @@ impl Registers {
         }
     }
 
+    // the banked stack pointers, R6 aliases one of these depending on the mode
+    pub fn ssp(&self) -> i16 {
+        self.ssp
+    }
+
+    pub fn usp(&self) -> i16 {
+        self.usp
+    }
+
+    pub fn set_ssp(&mut self, value: i16) {
+        self.ssp = value;
+    }
+
+    pub fn set_usp(&mut self, value: i16) {
+        self.usp = value;
+    }
+
+    // whether the program, the OS or the host wrote the register
+    pub fn is_initialized(&self, i: Register) -> bool {
+        self.initialized & (1 << i as u8) != 0
+    }
+
     pub fn get_mut(&mut self, i: Register) -> &mut i16 {
+        self.initialized |= 1 << i as u8;
+
         if i == Register::R6 {
             match self.mode {
                 PrivilegeMode::Supervisor => &mut self.ssp,
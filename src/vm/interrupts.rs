@@ -0,0 +1,75 @@
+use crate::vm::machine::{Machine, PrivilegeMode};
+
+// An asserted interrupt line, waiting for the PSR priority to drop below its own.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InterruptRequest {
+    pub vector: u8,
+    pub priority: u8,
+}
+
+impl Machine {
+    // Raises an interrupt. It is taken before the next instruction if `priority` is above the
+    // current PSR priority, otherwise it stays pending until an RTI lowers the priority enough.
+    // raising a vector that is already pending does nothing.
+    pub fn interrupt(&mut self, vector: u8, priority: u8) {
+        if self.pending_interrupts.iter().any(|r| r.vector == vector) {
+            return;
+        }
+
+        self.pending_interrupts.push(InterruptRequest {
+            vector,
+            priority: priority & 0b111,
+        });
+    }
+
+    pub fn pending_interrupts(&self) -> &[InterruptRequest] {
+        &self.pending_interrupts
+    }
+
+    // withdraws a pending request, returns false if it wasn't pending
+    pub fn cancel_interrupt(&mut self, vector: u8) -> bool {
+        let before = self.pending_interrupts.len();
+        self.pending_interrupts.retain(|r| r.vector != vector);
+        self.pending_interrupts.len() != before
+    }
+
+    // takes the most urgent pending interrupt that beats the PSR priority, if any.
+    // on equal priorities the one raised first wins
+    pub(crate) fn service_interrupts(&mut self) {
+        let mut best: Option<usize> = None;
+        for (i, request) in self.pending_interrupts.iter().enumerate() {
+            if request.priority <= self.priority {
+                continue;
+            }
+
+            if best.is_none_or(|b| request.priority > self.pending_interrupts[b].priority) {
+                best = Some(i);
+            }
+        }
+
+        if let Some(i) = best {
+            let request = self.pending_interrupts.remove(i);
+            self.enter_interrupt(request.vector, request.priority);
+        }
+    }
+
+    // saves PSR and PC on the supervisor stack and jumps through the interrupt vector table
+    pub(crate) fn enter_interrupt(&mut self, vector: u8, priority: u8) {
+        let addr = self.get_memory_at_unchecked(0x0100 + vector as u16);
+        if addr == 0 {
+            return;
+        }
+
+        let psr = self.encode_psr();
+
+        self.set_privilege(PrivilegeMode::Supervisor);
+        self.priority = priority;
+
+        let pc = self.ip;
+
+        self.stack_push(psr as i16);
+        self.stack_push(pc as i16);
+
+        self.ip = addr as u16;
+    }
+}
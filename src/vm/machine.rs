@@ -1,22 +1,36 @@
 use crate::bit_util::convert_str_to_i16_vec;
+use crate::io::{AssemblyInfo, DataInfo};
+use crate::vm::debugger::Debugger;
+use crate::vm::devices::callback::IoCallback;
+use crate::vm::devices::keyboard::{KEYBOARD_INTERRUPT_PRIORITY, KEYBOARD_INTERRUPT_VECTOR};
+use crate::vm::devices::{
+    DDR, DSR, Device, Display, KBDR, KBSR, Keyboard, MPR, MachineControl, MemoryProtection,
+    OverflowPolicy,
+};
+use crate::vm::diagnostics::InitChecker;
+use crate::vm::history::History;
 use crate::vm::instructions::Instruction::{
     Add, AddImmediate, And, AndImmediate, Branch, Jump, JumpSubroutine, JumpSubroutineRegister,
     Load, LoadEffectiveAddress, LoadIndirect, LoadRegister, Not, Reserved, ReturnFromInterrupt,
     Store, StoreIndirect, StoreRegister, Trap,
 };
-use crate::vm::instructions::{DesiredConditionFlags, Instruction, Register, Registers};
+use crate::vm::instructions::{Instruction, Register, Registers};
+use crate::vm::interrupts::InterruptRequest;
+pub use crate::vm::memory::{Memory, MemoryInit};
+use crate::vm::trace::Tracer;
+use std::any::Any;
 use std::collections::HashMap;
-use std::collections::hash_map::Keys;
-use std::ops::{Index, IndexMut};
-
-const KBSR: u16 = 0xFE00;
-const KBDR: u16 = 0xFE02;
+use std::ops::RangeInclusive;
 
-const DSR: u16 = 0xFE04;
-const DDR: u16 = 0xFE06;
 const PSR: u16 = 0xFFFC;
 
-const MCR: u16 = 0xFFFE;
+// entries of the device map, any other entry n is the device at index n - 1
+const NO_DEVICE: u8 = 0;
+const SHARED_DEVICES: u8 = u8::MAX;
+const MAX_DEVICES: usize = SHARED_DEVICES as usize - 1;
+
+// printed by the HALT trap routine, same as lc3tools
+pub const HALT_MESSAGE: &str = "\n\n--- Halting the LC-3 ---\n\n";
 
 const PRIVILEGE_EXC: u8 = 0x0;
 const ILLEGAL_OPCODE_EXC: u8 = 0x1;
@@ -27,6 +41,13 @@ pub enum Lc3Error {
     IllegalMemoryAccess(u16),
 }
 
+#[deprecated(note = "implement `Device` and attach it with `Machine::attach_device` instead")]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum MemoryModificationEvent {
+    Read(i16),
+    Write(i16),
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ConditionCode {
     Negative,
@@ -58,36 +79,21 @@ impl ConditionCode {
     }
 }
 
-pub struct Memory(HashMap<u16, i16>);
+// stands in for a device while it is taken out of the machine to run
+struct Detached;
 
-impl Memory {
-    #[allow(unused)]
-    fn entries(&self) -> Keys<'_, u16, i16> {
-        self.0.keys()
+impl Device for Detached {
+    fn name(&self) -> &'static str {
+        "detached"
     }
-}
-
-impl Index<u16> for Memory {
-    type Output = i16;
 
-    fn index(&self, index: u16) -> &Self::Output {
-        self.0.get(&index).unwrap_or(&0)
+    #[allow(clippy::reversed_empty_ranges)]
+    fn addresses(&self) -> RangeInclusive<u16> {
+        1..=0
     }
 }
 
-impl IndexMut<u16> for Memory {
-    fn index_mut(&mut self, index: u16) -> &mut Self::Output {
-        self.0.entry(index).or_insert(0)
-    }
-}
-
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
-pub enum MemoryModificationEvent {
-    Read(i16),
-    Write(i16),
-}
-
-pub struct Machine<'a> {
+pub struct Machine {
     pub registers: Registers,
     pub memory: Memory,
     pub ip: u16, // LC-3 is word addressable.
@@ -99,18 +105,35 @@ pub struct Machine<'a> {
     pub priority: u8,
 
     pub halted: bool,
+    pub instruction_count: u64,
 
     pub protect_system_memory: bool,
     pub protect_device_memory: bool,
-
-    memory_event_callbacks: HashMap<u16, fn(&mut Self, MemoryModificationEvent)>, // maybe a different data structure or hashing algorithm
+    // extra ranges user programs can't access, see `MachineBuilder::protect_range`
+    pub protected_ranges: Vec<RangeInclusive<u16>>,
+
+    pub debugger: Debugger,
+
+    pub(crate) devices: Vec<Box<dyn Device>>,
+    // which device responds to each address, see `NO_DEVICE` and `SHARED_DEVICES`
+    device_map: Box<[u8]>,
+    // by device, whether it wants `tick` called after the next instruction
+    ticking: Vec<bool>,
+    symbols: HashMap<String, u16>,
+    pub(crate) history: Option<History>,
+    pub(crate) init_checker: Option<InitChecker>,
+    pub(crate) tracer: Option<Tracer>,
+    pub(crate) pending_interrupts: Vec<InterruptRequest>,
+
+    // address of the instruction currently being evaluated
+    pub(crate) executing_pc: u16,
 }
 
 // Not sure if the condition code should start as the Zero flag.
 // according to https://www.cs.utexas.edu/~fussell/courses/cs310h/lectures/Lecture_10-310h.pdf it states
 // that exactly one condition code is set at all times. I suppose Zero is a sensible default.
 #[allow(unused)]
-impl<'a> Machine<'a> {
+impl Machine {
     pub fn new_x3000(instructions: &[Instruction]) -> Self {
         Self::new(0x3000, true, true, instructions)
     }
@@ -120,243 +143,115 @@ impl<'a> Machine<'a> {
         protect_system_memory: bool,
         protect_device_memory: bool,
         instructions: &[Instruction],
+    ) -> Self {
+        let mut machine = Self::new_without_os(
+            pc,
+            protect_system_memory,
+            protect_device_memory,
+            instructions,
+        );
+        machine.load_basic_os();
+        machine
+    }
+
+    // a machine with its devices, but nothing else in memory than the given instructions.
+    // the OS is expected to be loaded with `load_os`
+    pub fn new_without_os(
+        pc: u16,
+        protect_system_memory: bool,
+        protect_device_memory: bool,
+        instructions: &[Instruction],
     ) -> Self {
         // let mut memory = Vec::from_iter((0..orig).map(|_| 0));
         // for inst in instructions {
         //     memory.push(inst.encode() as i16);
         // }
 
-        let mut memory = HashMap::new();
+        let mut memory = Memory::new();
         for (i, instruction) in instructions.iter().enumerate() {
-            memory.insert(pc + i as u16, instruction.encode() as i16);
+            memory[pc.wrapping_add(i as u16)] = instruction.encode() as i16;
         }
 
         let mut machine = Self {
             registers: Registers::default(),
-            memory: Memory(memory),
+            memory,
             ip: pc,
             condition_code: ConditionCode::Zero,
             privilege: PrivilegeMode::User,
             priority: 0,
             halted: false,
+            instruction_count: 0,
             protect_system_memory,
             protect_device_memory,
+            protected_ranges: Vec::new(),
+
+            debugger: Debugger::default(),
 
-            memory_event_callbacks: HashMap::new(),
+            devices: Vec::new(),
+            device_map: vec![NO_DEVICE; 0x10000].into_boxed_slice(),
+            ticking: Vec::new(),
+            symbols: HashMap::new(),
+            history: None,
+            init_checker: None,
+            tracer: None,
+            pending_interrupts: Vec::new(),
+
+            executing_pc: pc,
         };
 
-        machine.load_basic_os();
+        machine.attach_device(Keyboard::default());
+        machine.attach_device(Display::default());
+        machine.attach_device(MachineControl);
+        machine.attach_device(MemoryProtection);
 
         machine
     }
 
-    pub fn load_basic_os(&mut self) {
-        self.set_memory_at_unchecked(0x0100 + ILLEGAL_OPCODE_EXC as u16, 0x0200);
-        self.set_span_at(
-            0x0200,
-            &[
-                LoadEffectiveAddress(Register::R0, (2).into()).encode() as i16,
-                Instruction::trap_puts().encode() as i16,
-                Instruction::trap_halt().encode() as i16,
-            ],
-        );
-        self.string_set(0x0203, "[exc] Illegal opcode\n\0");
-
-        self.set_memory_at_unchecked(0x0100 + PRIVILEGE_EXC as u16, 0x219);
-        self.set_span_at(
-            0x219,
-            &[
-                LoadEffectiveAddress(Register::R0, (2).into()).encode() as i16,
-                Instruction::trap_puts().encode() as i16,
-                Instruction::trap_halt().encode() as i16,
-            ],
-        );
-        self.string_set(0x21c, "[exc] invalid privilege\n\0");
-
-        self.set_memory_at_unchecked(0x0100 + ACV_EXC as u16, 0x235);
-        self.set_span_at(
-            0x235,
-            &[
-                LoadEffectiveAddress(Register::R0, (2).into()).encode() as i16,
-                Instruction::trap_puts().encode() as i16,
-                Instruction::trap_halt().encode() as i16,
-            ],
-        );
-        self.string_set(0x238, "[exc] ACV\n\0");
-
-        // automatically reset status bit after a read
-        self.add_io_callback(KBDR, |machine, event| {
-            if let MemoryModificationEvent::Read(_) = event {
-                machine.memory[KBSR] &= !(1 << 15); // clear 15th bit
-            }
-        });
-
-        self.set_keyboard_interrupts(true);
-
-        self.add_io_callback(DDR, |machine, event| {
-            if let MemoryModificationEvent::Write(_) = event {
-                machine.memory[DSR] &= !(1 << 15); // clear 15th bit
-            }
-        });
-
-        // GETC trap vector
-        self.set_memory_at_unchecked(0x20, 0x0244);
-        self.set_span_at(
-            0x0244,
-            &[
-                LoadIndirect(Register::R0, 93.into()).encode() as i16,
-                Branch(
-                    DesiredConditionFlags {
-                        positive: true,
-                        zero: true,
-                        negative: false,
-                    },
-                    (-2).into(),
-                )
-                .encode() as i16,
-                LoadIndirect(Register::R0, 92.into()).encode() as i16,
-                ReturnFromInterrupt.encode() as i16,
-            ],
-        );
-
-        self.set_memory_at_unchecked(0x02A2, KBSR as i16);
-        self.set_memory_at_unchecked(0x02A3, KBDR as i16);
-
-        // OUT trap vector
-        self.set_memory_at_unchecked(0x21, 0x0248);
-        self.set_span_at(
-            0x0248,
-            &[
-                // move stack pointer
-                AddImmediate(Register::R6, Register::R6, (-1).into()).encode() as i16,
-                // push R0 onto stack
-                StoreRegister(Register::R0, Register::R6, (0).into()).encode() as i16,
-                // load DSR into R0
-                LoadIndirect(Register::R0, (0x02A4 - (0x0248 + 2) - 1).into()).encode() as i16,
-                Branch(
-                    DesiredConditionFlags {
-                        negative: false,
-                        zero: true,
-                        positive: true,
-                    },
-                    (-2).into(),
-                )
-                .encode() as i16,
-                // pop data from stack
-                LoadRegister(Register::R0, Register::R6, (0).into()).encode() as i16,
-                AddImmediate(Register::R6, Register::R6, (1).into()).encode() as i16,
-                StoreIndirect(Register::R0, (0x02A5 - (0x0248 + 6) - 1).into()).encode() as i16,
-                ReturnFromInterrupt.encode() as i16,
-            ],
-        );
-
-        self.set_memory_at_unchecked(0x02A4, DSR as i16);
-        self.set_memory_at_unchecked(0x02A5, DDR as i16);
-
-        self.set_display_status(true);
-
-        // PUTS trap vector
-
-        self.set_memory_at_unchecked(0x22, 0x0250);
-        self.set_span_at(
-            0x0250,
-            &[
-                // push R0 onto stack
-                AddImmediate(Register::R6, Register::R6, (-1).into()).encode() as i16,
-                StoreRegister(Register::R0, Register::R6, (0).into()).encode() as i16,
-                // push R1 onto stack
-                AddImmediate(Register::R6, Register::R6, (-1).into()).encode() as i16,
-                StoreRegister(Register::R1, Register::R6, (0).into()).encode() as i16,
-                // copy R0 to R1
-                AddImmediate(Register::R1, Register::R0, (0).into()).encode() as i16,
-                // load char
-                LoadRegister(Register::R0, Register::R1, 0.into()).encode() as i16,
-                // if zero we jump to end
-                Branch(
-                    DesiredConditionFlags {
-                        negative: false,
-                        zero: true,
-                        positive: false,
-                    },
-                    (3).into(),
-                )
-                .encode() as i16,
-                // otherwise we print first char
-                Instruction::trap_out().encode() as i16,
-                // update pointer to get address of next char
-                AddImmediate(Register::R1, Register::R1, (1).into()).encode() as i16,
-                // jump back up to load register
-                Branch(
-                    DesiredConditionFlags {
-                        negative: true,
-                        zero: true,
-                        positive: true,
-                    },
-                    (-5).into(),
-                )
-                .encode() as i16,
-                // after the loop
-                // pop off R1
-                LoadRegister(Register::R1, Register::R6, (0).into()).encode() as i16,
-                AddImmediate(Register::R6, Register::R6, (1).into()).encode() as i16,
-                // pop off R0
-                LoadRegister(Register::R0, Register::R6, (0).into()).encode() as i16,
-                AddImmediate(Register::R6, Register::R6, (1).into()).encode() as i16,
-                ReturnFromInterrupt.encode() as i16,
-            ],
-        );
+    pub fn load_assembly(&mut self, info: &AssemblyInfo) {
+        for datum in &info.data {
+            self.load_data(datum);
+        }
 
-        // IN trap vector
-        self.set_memory_at_unchecked(0x23, 0x025f);
-        self.set_span_at(
-            0x025f,
-            &[
-                Instruction::trap_puts().encode() as i16,
-                Instruction::trap_get_c().encode() as i16,
-                Instruction::trap_out().encode() as i16,
-                ReturnFromInterrupt.encode() as i16,
-            ],
+        self.load_symbols(
+            info.symbols
+                .iter()
+                .map(|(label, address)| (label.clone(), *address)),
         );
-
-        // Machine Control Register
-        // set 15th bit to 1.
-        self.set_memory_at_unchecked(MCR, 1 << 15);
-
-        self.add_io_callback(MCR, |machine, event| {
-            if let MemoryModificationEvent::Write(value) = event
-                && value >= 0
-            {
-                // 15th bit is cleared
-                // time to halt
-                machine.halted = true;
-            }
-        });
     }
 
-    pub fn interrupt(&mut self, vector: u8, urgency: u8) {
-        if urgency < self.priority {
-            return;
+    pub(crate) fn load_data(&mut self, datum: &DataInfo) {
+        self.set_span_at(datum.orig, &datum.data);
+
+        for index in &datum.uninitialized {
+            self.memory.clear(datum.orig.wrapping_add(*index as u16));
         }
+    }
 
-        let vector = (vector as u16) + 0x0100;
+    // changes what untouched memory (and `????` words loaded from then on) holds
+    pub fn set_memory_init(&mut self, init: MemoryInit) {
+        self.memory.set_init(init);
+    }
 
-        let addr = self.get_memory_at_unchecked(vector);
-        if addr == 0 {
-            return;
+    pub fn load_symbols(&mut self, symbols: impl IntoIterator<Item = (String, u16)>) {
+        for (label, address) in symbols {
+            self.symbols.insert(label.to_lowercase(), address);
         }
+    }
 
-        // kinda duplicated code, maybe fix
-        let psr = self.encode_psr();
-
-        self.set_privilege(PrivilegeMode::Supervisor);
-        self.priority = urgency;
+    // labels are case insensitive
+    pub fn symbol_address(&self, label: &str) -> Option<u16> {
+        self.symbols.get(&label.to_lowercase()).copied()
+    }
 
-        let pc = self.ip;
+    pub fn symbols(&self) -> &HashMap<String, u16> {
+        &self.symbols
+    }
 
-        self.stack_push(psr as i16);
-        self.stack_push(pc as i16);
+    fn exception(&mut self, vector: u8) {
+        self.debugger.record_exception(vector);
 
-        self.ip = addr as u16;
+        // exceptions can't wait, they are taken right away
+        self.enter_interrupt(vector, 7);
     }
 
     // true => data set
@@ -369,18 +264,73 @@ impl<'a> Machine<'a> {
         self.memory[DSR] < 0 // 15th bit is set
     }
 
+    // The key goes straight to KBDR when it's free, otherwise it waits in the keyboard's
+    // type-ahead buffer. Returns false if the key was dropped.
     pub fn set_keyboard_key(&mut self, data: u16) -> bool {
-        if !self.get_keyboard_status() {
-            self.memory[KBDR] = data as i16;
-            self.memory[KBSR] |= (1 << 15); // 15th bit is set.
+        let free = !self.get_keyboard_status();
+
+        match self.device_mut::<Keyboard>() {
+            Some(keyboard) if !free || keyboard.pending() > 0 => keyboard.push(data),
+            None if !free => false,
+            _ => {
+                self.deliver_key(data);
+                true
+            }
+        }
+    }
 
-            if self.get_keyboard_interrupt_enable_bit() {
-                self.interrupt(0x80, 4);
+    // resizes the attached keyboard's type-ahead buffer, returns false if there is no keyboard
+    pub fn set_type_ahead(&mut self, capacity: usize, overflow: OverflowPolicy) -> bool {
+        match self.device_mut::<Keyboard>() {
+            Some(keyboard) => {
+                keyboard.set_capacity(capacity);
+                keyboard.set_overflow(overflow);
+                true
             }
+            None => false,
+        }
+    }
+
+    // whether `set_keyboard_key` would keep another key
+    pub fn keyboard_has_room(&self) -> bool {
+        let free = !self.get_keyboard_status();
+
+        match self.device::<Keyboard>() {
+            Some(keyboard) => free || !keyboard.is_full(),
+            None => free,
+        }
+    }
+
+    // queues all of the input for the program, regardless of the type-ahead capacity.
+    // returns false if there is no keyboard to hold it
+    pub fn preload_input(&mut self, input: &[u8]) -> bool {
+        let Some(keyboard) = self.device_mut::<Keyboard>() else {
+            return false;
+        };
+        keyboard.preload(input.iter().map(|&key| key as u16));
+
+        if !self.get_keyboard_status()
+            && let Some(key) = self.device_mut::<Keyboard>().and_then(Keyboard::pop)
+        {
+            self.deliver_key(key);
+        }
+        true
+    }
 
-            true
+    // puts the key in KBDR, like it was just typed
+    pub(crate) fn deliver_key(&mut self, data: u16) {
+        self.memory[KBDR] = data as i16;
+        self.memory[KBSR] |= (1 << 15); // 15th bit is set.
+        self.update_keyboard_interrupt();
+    }
+
+    // the keyboard's interrupt line is asserted while a key is ready and interrupts are enabled,
+    // a request the line no longer asserts is withdrawn
+    pub(crate) fn update_keyboard_interrupt(&mut self) {
+        if self.get_keyboard_status() && self.get_keyboard_interrupt_enable_bit() {
+            self.interrupt(KEYBOARD_INTERRUPT_VECTOR, KEYBOARD_INTERRUPT_PRIORITY);
         } else {
-            false
+            self.cancel_interrupt(KEYBOARD_INTERRUPT_VECTOR);
         }
     }
 
@@ -389,9 +339,13 @@ impl<'a> Machine<'a> {
     }
 
     pub fn set_keyboard_interrupts(&mut self, enable: bool) {
-        let mask = ((enable as i16) & 0b1) << 14;
-        self.memory[KBSR] &= !mask;
-        self.memory[KBSR] |= mask;
+        let mask = 1 << 14;
+        if enable {
+            self.memory[KBSR] |= mask;
+        } else {
+            self.memory[KBSR] &= !mask;
+        }
+        self.update_keyboard_interrupt();
     }
 
     pub fn get_display_data(&self) -> u16 {
@@ -400,21 +354,16 @@ impl<'a> Machine<'a> {
 
     pub fn set_display_status(&mut self, ready: bool) {
         let ready = ready as u16;
-        self.memory[DSR] = (self.memory[KBSR] & !(1 << 15)) | (ready << 15) as i16;
+        self.memory[DSR] = (self.memory[DSR] & !(1 << 15)) | (ready << 15) as i16;
     }
 
     pub fn get_display_interrupt_enable_bit(&self) -> bool {
         ((self.memory[DSR] >> 14) & 0b1) == 1
     }
 
+    // next character the program has output, if any
     pub fn poll_display_data(&mut self) -> Option<u16> {
-        if !self.get_display_status() {
-            self.set_display_status(true);
-            let data = self.get_display_data();
-            Some(data)
-        } else {
-            None
-        }
+        self.device_mut::<Display>()?.take_output()
     }
 
     pub fn set_privilege(&mut self, privilege: PrivilegeMode) {
@@ -431,8 +380,32 @@ impl<'a> Machine<'a> {
     }
 
     pub fn is_address_protected(&self, address: u16) -> bool {
-        (self.protect_device_memory && self.is_address_in_io_section(address))
+        // the MPR is privileged however the rest is set up, or user programs could open blocks
+        if address == MPR {
+            return true;
+        }
+
+        let protected = (self.protect_device_memory && self.is_address_in_io_section(address))
             || (self.protect_system_memory && self.is_address_in_system_section(address))
+            || !self.is_block_accessible(address)
+            || self
+                .protected_ranges
+                .iter()
+                .any(|range| range.contains(&address));
+
+        protected && !self.is_address_user_accessible(address)
+    }
+
+    // whether the MPR lets user programs into the 4K block holding the address
+    pub fn is_block_accessible(&self, address: u16) -> bool {
+        (self.memory[MPR] as u16 >> (address >> 12)) & 1 == 1
+    }
+
+    // registers of devices that user programs are allowed to use directly
+    pub fn is_address_user_accessible(&self, address: u16) -> bool {
+        self.devices
+            .iter()
+            .any(|device| device.user_accessible() && device.addresses().contains(&address))
     }
 
     // Set data in the IO section of memory (0xFE00 to 0xFFFF)
@@ -442,16 +415,162 @@ impl<'a> Machine<'a> {
         self.memory[desired] = data;
     }
 
-    pub fn invoke_io_event(&mut self, address: u16, event: MemoryModificationEvent) {
-        let Some(callback) = self.memory_event_callbacks.get(&address) else {
-            return;
-        };
+    pub fn attach_device(&mut self, device: impl Device) {
+        self.attach_boxed_device(Box::new(device));
+    }
+
+    // for devices picked at runtime
+    pub fn attach_boxed_device(&mut self, mut device: Box<dyn Device>) {
+        device.attach(self);
+
+        let index = self.devices.len();
+        assert!(
+            index < MAX_DEVICES,
+            "no more than {MAX_DEVICES} devices can be attached"
+        );
+
+        for address in device.addresses() {
+            let slot = &mut self.device_map[address as usize];
+            *slot = if *slot == NO_DEVICE {
+                index as u8 + 1
+            } else {
+                SHARED_DEVICES
+            };
+        }
 
-        callback(self, event);
+        self.ticking.push(device.ticks());
+        self.devices.push(device);
     }
 
+    // Kept for programs written against the callback API, the callback becomes a device
+    // responding to `address`. Adding another callback for the same address replaces it,
+    // and like before only addresses in the IO section are ever called back.
+    #[deprecated(note = "implement `Device` and attach it with `Machine::attach_device` instead")]
+    #[allow(deprecated)]
     pub fn add_io_callback(&mut self, address: u16, func: fn(&mut Self, MemoryModificationEvent)) {
-        self.memory_event_callbacks.insert(address, func);
+        if !self.is_address_in_io_section(address) {
+            return;
+        }
+
+        let existing = self.devices.iter_mut().find_map(|device| {
+            (&mut **device as &mut dyn Any)
+                .downcast_mut::<IoCallback>()
+                .filter(|callback| callback.address == address)
+        });
+
+        match existing {
+            Some(existing) => existing.callback = func,
+            None => self.attach_device(IoCallback {
+                address,
+                callback: func,
+            }),
+        }
+    }
+
+    #[deprecated(note = "implement `Device` and attach it with `Machine::attach_device` instead")]
+    #[allow(deprecated)]
+    pub fn invoke_io_event(&mut self, address: u16, event: MemoryModificationEvent) {
+        let callback = self.devices.iter().find_map(|device| {
+            (&**device as &dyn Any)
+                .downcast_ref::<IoCallback>()
+                .filter(|callback| callback.address == address)
+                .map(|callback| callback.callback)
+        });
+
+        if let Some(callback) = callback {
+            callback(self, event);
+        }
+    }
+
+    // first attached device of type T
+    pub fn device<T: Device>(&self) -> Option<&T> {
+        self.devices
+            .iter()
+            .find_map(|device| (&**device as &dyn Any).downcast_ref::<T>())
+    }
+
+    // the device may change in ways that give it work to do, so it is ticked at least once more
+    pub fn device_mut<T: Device>(&mut self) -> Option<&mut T> {
+        let index = self
+            .devices
+            .iter()
+            .position(|device| (&**device as &dyn Any).is::<T>())?;
+        self.ticking[index] = true;
+
+        (&mut *self.devices[index] as &mut dyn Any).downcast_mut::<T>()
+    }
+
+    pub fn is_address_mapped_to_device(&self, address: u16) -> bool {
+        self.device_map[address as usize] != NO_DEVICE
+    }
+
+    // Devices are taken out of the machine while they run, so they can freely use the machine.
+    fn with_device<R>(
+        &mut self,
+        index: usize,
+        f: impl FnOnce(&mut Self, &mut dyn Device) -> R,
+    ) -> R {
+        let mut device = std::mem::replace(&mut self.devices[index], Box::new(Detached));
+        let result = f(self, &mut *device);
+        self.ticking[index] = device.ticks();
+        self.devices[index] = device;
+
+        result
+    }
+
+    // indices of the devices responding to the address, when more than one does
+    fn shared_devices(&self, address: u16) -> Vec<usize> {
+        (0..self.devices.len())
+            .filter(|&i| self.devices[i].addresses().contains(&address))
+            .collect()
+    }
+
+    fn device_read(&mut self, address: u16, value: i16) -> i16 {
+        match self.device_map[address as usize] {
+            NO_DEVICE => value,
+            SHARED_DEVICES => {
+                let mut value = value;
+                for index in self.shared_devices(address) {
+                    value = self.with_device(index, |machine, device| {
+                        device.read(machine, address, value)
+                    });
+                }
+                value
+            }
+            slot => self.with_device(slot as usize - 1, |machine, device| {
+                device.read(machine, address, value)
+            }),
+        }
+    }
+
+    fn device_write(&mut self, address: u16, value: i16) {
+        match self.device_map[address as usize] {
+            NO_DEVICE => {}
+            SHARED_DEVICES => {
+                for index in self.shared_devices(address) {
+                    self.with_device(index, |machine, device| {
+                        device.write(machine, address, value)
+                    });
+                }
+            }
+            slot => self.with_device(slot as usize - 1, |machine, device| {
+                device.write(machine, address, value)
+            }),
+        }
+    }
+
+    fn tick_devices(&mut self) {
+        for index in 0..self.devices.len() {
+            if self.ticking[index] {
+                self.with_device(index, |machine, device| device.tick(machine));
+            }
+        }
+    }
+
+    pub(crate) fn rewind_devices(&mut self) {
+        for index in 0..self.devices.len() {
+            self.with_device(index, |machine, device| device.rewind(machine));
+        }
     }
 
     pub fn set_memory_at_unchecked(&mut self, address: u16, value: i16) {
@@ -467,15 +586,20 @@ impl<'a> Machine<'a> {
             return Err(Lc3Error::IllegalMemoryAccess(index));
         }
 
+        if self.tracer.is_some() {
+            self.trace_write(index, value);
+        }
+
         if index == PSR {
             self.decode_psr(value as u16);
             return Ok(());
         }
 
         self.memory[index] = value;
+        self.device_write(index, value);
 
-        if self.is_address_in_io_section(index) {
-            self.invoke_io_event(index, MemoryModificationEvent::Write(value));
+        if self.debugger.has_watchpoints() {
+            self.debugger.check_write(index, value, self.executing_pc);
         }
 
         Ok(())
@@ -487,13 +611,26 @@ impl<'a> Machine<'a> {
         }
 
         if index == PSR {
-            return Ok(self.encode_psr() as i16);
+            let psr = self.encode_psr() as i16;
+            if self.tracer.is_some() {
+                self.trace_read(index, psr);
+            }
+            return Ok(psr);
+        }
+
+        if self.init_checker.is_some() {
+            self.check_memory_read(index);
         }
 
         let val = self.memory[index];
+        let val = self.device_read(index, val);
 
-        if self.is_address_in_io_section(index) {
-            self.invoke_io_event(index, MemoryModificationEvent::Read(val));
+        if self.tracer.is_some() {
+            self.trace_read(index, val);
+        }
+
+        if self.debugger.has_watchpoints() {
+            self.debugger.check_read(index, val, self.executing_pc);
         }
 
         Ok(val)
@@ -516,14 +653,44 @@ impl<'a> Machine<'a> {
     }
 
     pub fn step(&mut self) {
-        let instr = self.memory[self.ip];
+        if self.history.is_some() {
+            self.step_recorded();
+        } else {
+            self.execute_step();
+        }
+    }
+
+    pub(crate) fn execute_step(&mut self) {
+        if !self.pending_interrupts.is_empty() {
+            self.service_interrupts();
+        }
+
+        self.executing_pc = self.ip;
+
+        let word = self.memory[self.ip] as u16;
+        let instr = self.memory.instruction_at(self.ip);
         self.ip += 1; // ip points to the next instruction
+        self.instruction_count += 1;
 
-        if let Err(err) = self.evaluate(Instruction::decode(instr as u16)) {
+        if self.init_checker.is_some() {
+            self.check_register_reads(instr);
+        }
+
+        if self.tracer.is_some() {
+            self.trace_begin(word, instr);
+        }
+
+        if let Err(err) = self.evaluate(instr) {
             match err {
-                Lc3Error::IllegalMemoryAccess(_) => self.interrupt(ACV_EXC, 7),
+                Lc3Error::IllegalMemoryAccess(_) => self.exception(ACV_EXC),
             }
         }
+
+        if self.tracer.is_some() {
+            self.trace_end();
+        }
+
+        self.tick_devices();
     }
 
     pub fn add_to_ip(&mut self, offset: i16) {
@@ -668,7 +835,7 @@ impl<'a> Machine<'a> {
 
                     self.decode_psr(psr);
                 } else {
-                    self.interrupt(PRIVILEGE_EXC, 7);
+                    self.exception(PRIVILEGE_EXC);
                 }
             }
 
@@ -693,41 +860,28 @@ impl<'a> Machine<'a> {
 
             Trap(vector) => self.handle_trap(vector),
 
-            Reserved => self.interrupt(ILLEGAL_OPCODE_EXC, 7),
+            Reserved => self.exception(ILLEGAL_OPCODE_EXC),
         };
 
         Ok(())
     }
 
-    fn handle_trap(&mut self, vec: u8) {
-        // TODO, implement trap vectors in the Machine's instructions itself,
-        // instead of implementing it within Rust
-        match vec {
-            // 0x23 => todo!("in"),
-            0x24 => todo!("putsp"),
-
-            // halt
-            0x25 => {
-                // technically this should modify the MCR, but whatever
-                self.halted = true;
-            }
-            vector => {
-                // this part is not implemented according to the ISA pdf,
-                // but rather the book 'Introduction To Computing Systems: From Bits & Gates To C/C++ & Beyond (3rd Edition)'
+    fn handle_trap(&mut self, vector: u8) {
+        // this part is not implemented according to the ISA pdf,
+        // but rather the book 'Introduction To Computing Systems: From Bits & Gates To C/C++ & Beyond (3rd Edition)'
+        // the routines themselves are part of the OS, see `load_basic_os`
 
-                let psr = self.encode_psr();
+        let psr = self.encode_psr();
 
-                self.set_privilege(PrivilegeMode::Supervisor);
+        self.set_privilege(PrivilegeMode::Supervisor);
 
-                let pc = self.ip;
+        let pc = self.ip;
 
-                self.stack_push(psr as i16);
-                self.stack_push(pc as i16);
+        self.stack_push(psr as i16);
+        self.stack_push(pc as i16);
 
-                let desired = self.memory[vector as u16];
-                self.ip = desired as u16;
-            }
-        }
+        let desired = self.memory[vector as u16];
+        self.ip = desired as u16;
     }
 
     pub fn stack_push(&mut self, val: i16) {
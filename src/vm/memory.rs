@@ -0,0 +1,258 @@
+use std::ops::{Index, IndexMut};
+
+use crate::bit_util::splitmix64;
+use crate::vm::instructions::Instruction;
+
+// LC-3 has a 16-bit address space of 16-bit words.
+pub const MEMORY_SIZE: usize = 1 << 16;
+
+const BITSET_WORDS: usize = MEMORY_SIZE / 64;
+
+// What a word holds before anything is written to it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MemoryInit {
+    #[default]
+    Zero,
+    Pattern(i16),
+
+    // the same seed always gives the same contents
+    Random(u64),
+}
+
+impl MemoryInit {
+    pub fn value_at(&self, address: u16) -> i16 {
+        match *self {
+            MemoryInit::Zero => 0,
+            MemoryInit::Pattern(value) => value,
+            MemoryInit::Random(seed) => {
+                // jump straight to the address'th output, so the value doesn't depend on access order
+                let mut state =
+                    seed.wrapping_add((address as u64).wrapping_mul(0x9E3779B97F4A7C15));
+                splitmix64(&mut state) as i16
+            }
+        }
+    }
+}
+
+// Flat backing store for the whole address space. Every word reads as its `MemoryInit` value
+// until it is written, and each written address is recorded in a bitset so dumps only need to
+// visit touched regions.
+pub struct Memory {
+    words: Box<[i16]>,
+    touched: Box<[u64]>,
+    init: MemoryInit,
+
+    // words already decoded as instructions, by address. writing a word drops its entry,
+    // so self-modifying code sees the new instruction
+    decoded: Box<[Option<Instruction>]>,
+    cache_decoded: bool,
+
+    // while recording, every write logs what it overwrote
+    journal: Option<Vec<JournalEntry>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub address: u16,
+    pub previous: i16,
+    pub was_touched: bool,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::with_init(MemoryInit::Zero)
+    }
+
+    pub fn with_init(init: MemoryInit) -> Self {
+        let mut memory = Self {
+            words: vec![0; MEMORY_SIZE].into_boxed_slice(),
+            touched: vec![0; BITSET_WORDS].into_boxed_slice(),
+            init: MemoryInit::Zero,
+            decoded: vec![None; MEMORY_SIZE].into_boxed_slice(),
+            cache_decoded: true,
+            journal: None,
+        };
+        memory.set_init(init);
+        memory
+    }
+
+    pub fn init(&self) -> MemoryInit {
+        self.init
+    }
+
+    // changes the policy and refills every untouched word with it
+    pub fn set_init(&mut self, init: MemoryInit) {
+        self.init = init;
+        self.decoded.fill(None);
+
+        for address in 0..=u16::MAX {
+            if !self.is_touched(address) {
+                self.words[address as usize] = init.value_at(address);
+            }
+        }
+    }
+
+    // forgets the word was ever written, it goes back to its initial value
+    pub fn clear(&mut self, address: u16) {
+        self.record(address);
+
+        let index = address as usize;
+        self.words[index] = self.init.value_at(address);
+        self.decoded[index] = None;
+        self.touched[index / 64] &= !(1 << (index % 64));
+    }
+
+    // on by default, turning it off decodes every fetch again, to measure what the cache saves
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cache_decoded = enabled;
+        self.decoded.fill(None);
+    }
+
+    // the word at `address` as an instruction, decoded once until the word changes
+    pub fn instruction_at(&mut self, address: u16) -> Instruction {
+        let index = address as usize;
+        if !self.cache_decoded {
+            return Instruction::decode(self.words[index] as u16);
+        }
+
+        match self.decoded[index] {
+            Some(instruction) => instruction,
+            None => {
+                let instruction = Instruction::decode(self.words[index] as u16);
+                self.decoded[index] = Some(instruction);
+                instruction
+            }
+        }
+    }
+
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    // stops recording and returns the writes made since `start_journal`, oldest first
+    pub fn take_journal(&mut self) -> Vec<JournalEntry> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    // undoes journaled writes, newest first
+    pub fn undo(&mut self, entries: &[JournalEntry]) {
+        for entry in entries.iter().rev() {
+            let address = entry.address as usize;
+            self.words[address] = entry.previous;
+            self.decoded[address] = None;
+
+            if entry.was_touched {
+                self.touched[address / 64] |= 1 << (address % 64);
+            } else {
+                self.touched[address / 64] &= !(1 << (address % 64));
+            }
+        }
+    }
+
+    pub fn is_touched(&self, address: u16) -> bool {
+        let address = address as usize;
+        (self.touched[address / 64] >> (address % 64)) & 0b1 == 1
+    }
+
+    // journals the word before it changes
+    fn record(&mut self, address: u16) {
+        let was_touched = self.is_touched(address);
+        if let Some(journal) = &mut self.journal {
+            journal.push(JournalEntry {
+                address,
+                previous: self.words[address as usize],
+                was_touched,
+            });
+        }
+    }
+
+    fn mark_touched(&mut self, address: u16) {
+        let address = address as usize;
+        self.touched[address / 64] |= 1 << (address % 64);
+    }
+
+    // every touched address along with its value, in ascending address order
+    pub fn touched(&self) -> impl Iterator<Item = (u16, i16)> + '_ {
+        self.regions().flat_map(|(start, words)| {
+            words
+                .iter()
+                .enumerate()
+                .map(move |(i, value)| (start.wrapping_add(i as u16), *value))
+        })
+    }
+
+    // contiguous runs of touched addresses, as (start address, words)
+    pub fn regions(&self) -> Regions<'_> {
+        Regions {
+            memory: self,
+            next: 0,
+        }
+    }
+
+    // finds the first address at or after `from` whose touched bit equals `state`
+    fn find_from(&self, from: usize, state: bool) -> Option<usize> {
+        let mut chunk = from / 64;
+        if chunk >= BITSET_WORDS {
+            return None;
+        }
+
+        let flip = if state { 0 } else { u64::MAX };
+
+        // ignore the bits below `from` in the first chunk
+        let mut bits = (self.touched[chunk] ^ flip) & (u64::MAX << (from % 64));
+
+        loop {
+            if bits != 0 {
+                return Some(chunk * 64 + bits.trailing_zeros() as usize);
+            }
+
+            chunk += 1;
+            if chunk >= BITSET_WORDS {
+                return None;
+            }
+
+            bits = self.touched[chunk] ^ flip;
+        }
+    }
+}
+
+pub struct Regions<'a> {
+    memory: &'a Memory,
+    next: usize,
+}
+
+impl<'a> Iterator for Regions<'a> {
+    type Item = (u16, &'a [i16]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.memory.find_from(self.next, true)?;
+        let end = self.memory.find_from(start, false).unwrap_or(MEMORY_SIZE);
+
+        self.next = end;
+
+        Some((start as u16, &self.memory.words[start..end]))
+    }
+}
+
+impl Index<u16> for Memory {
+    type Output = i16;
+
+    fn index(&self, index: u16) -> &Self::Output {
+        &self.words[index as usize]
+    }
+}
+
+impl IndexMut<u16> for Memory {
+    fn index_mut(&mut self, index: u16) -> &mut Self::Output {
+        self.record(index);
+        self.mark_touched(index);
+        self.decoded[index as usize] = None;
+        &mut self.words[index as usize]
+    }
+}
@@ -1,2 +1,14 @@
+pub mod builder;
+pub mod console;
+pub mod debugger;
+pub mod devices;
+pub mod diagnostics;
+pub mod handle;
+pub mod history;
 pub mod instructions;
+pub mod interrupts;
 pub mod machine;
+pub mod memory;
+pub mod os;
+pub mod snapshot;
+pub mod trace;
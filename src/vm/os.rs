@@ -0,0 +1,42 @@
+use crate::io::{AssemblyInfo, read_complex};
+use crate::vm::machine::Machine;
+
+// assembly source of the basic OS, `BASIC_OS_IMAGE` is this assembled
+pub const BASIC_OS_SOURCE: &str = include_str!("os/basic.asm");
+pub const BASIC_OS_IMAGE: &[u8] = include_bytes!("os/basic.obj");
+
+// an OS image may label a word SAVED_SSP holding the initial supervisor stack pointer
+pub const SAVED_SSP_LABEL: &str = "saved_ssp";
+
+// the basic OS keeps its supervisor stack just above its code instead of at the top of system
+// memory, so a trap doesn't overwrite the words right below x3000 that programs may use
+pub const BASIC_OS_SSP: u16 = 0x0800;
+
+pub fn basic_os() -> AssemblyInfo {
+    read_complex::read(BASIC_OS_IMAGE)
+}
+
+impl Machine {
+    pub fn load_basic_os(&mut self) {
+        self.load_os(&basic_os());
+        self.registers.set_ssp(BASIC_OS_SSP as i16);
+    }
+
+    // loads an OS image: trap vector table, interrupt vector table, handlers, and so on.
+    // unlike `load_assembly` its symbols are not made available to the debugger
+    pub fn load_os(&mut self, os: &AssemblyInfo) {
+        // interrupts the OS has no handler for are ignored, whatever the memory init holds
+        for entry in 0x0100..=0x01FF {
+            self.memory[entry] = 0;
+        }
+
+        for datum in &os.data {
+            self.load_data(datum);
+        }
+
+        if let Some(address) = os.symbols.get(SAVED_SSP_LABEL) {
+            let ssp = self.get_memory_at_unchecked(*address);
+            self.registers.set_ssp(ssp);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+; The basic operating system loaded by Machine::new.
+; After editing, reassemble it with:
+;     lc3-cli asm src/vm/os/basic.asm -o src/vm/os/basic.obj
+
+; trap vector table
+.orig x0020
+    .fill GETC_ROUTINE
+    .fill OUT_ROUTINE
+    .fill PUTS_ROUTINE
+    .fill IN_ROUTINE
+    .fill PUTSP_ROUTINE
+    .fill HALT_ROUTINE
+.end
+
+; interrupt vector table, only the exceptions
+.orig x0100
+    .fill PRIVILEGE         ; privilege mode violation
+    .fill ILLEGAL_OPCODE    ; illegal opcode
+    .fill ACV               ; access control violation
+.end
+
+.orig x0200
+
+; exception handlers, report and halt
+ILLEGAL_OPCODE
+    LEA R0, ILLEGAL_OPCODE_MSG
+    PUTS
+    HALT
+ILLEGAL_OPCODE_MSG .stringz "[exc] Illegal opcode\n"
+
+PRIVILEGE
+    LEA R0, PRIVILEGE_MSG
+    PUTS
+    HALT
+PRIVILEGE_MSG .stringz "[exc] invalid privilege\n"
+
+ACV
+    LEA R0, ACV_MSG
+    PUTS
+    HALT
+ACV_MSG .stringz "[exc] ACV\n"
+
+; GETC, waits for a key and reads it into R0
+GETC_ROUTINE
+    LDI R0, KBSR_ADDR
+    BRzp GETC_ROUTINE
+    LDI R0, KBDR_ADDR
+    RTI
+
+; OUT, writes R0 to the display once it is ready
+OUT_ROUTINE
+    ADD R6, R6, #-1
+    STR R0, R6, #0
+OUT_WAIT
+    LDI R0, DSR_ADDR
+    BRzp OUT_WAIT
+    LDR R0, R6, #0
+    ADD R6, R6, #1
+    STI R0, DDR_ADDR
+    RTI
+
+; PUTS, writes the null terminated string at R0
+PUTS_ROUTINE
+    ADD R6, R6, #-1
+    STR R0, R6, #0
+    ADD R6, R6, #-1
+    STR R1, R6, #0
+    ADD R1, R0, #0
+PUTS_LOOP
+    LDR R0, R1, #0
+    BRz PUTS_DONE
+    OUT
+    ADD R1, R1, #1
+    BRnzp PUTS_LOOP
+PUTS_DONE
+    LDR R1, R6, #0
+    ADD R6, R6, #1
+    LDR R0, R6, #0
+    ADD R6, R6, #1
+    RTI
+
+; IN, prints the prompt at R0 then reads and echoes a key
+IN_ROUTINE
+    PUTS
+    GETC
+    OUT
+    RTI
+
+; PUTSP, writes the string at R0 packed two characters per word, low byte first
+PUTSP_ROUTINE
+    ADD R6, R6, #-1
+    STR R0, R6, #0
+    ADD R6, R6, #-1
+    STR R1, R6, #0
+    ADD R6, R6, #-1
+    STR R2, R6, #0
+    ADD R6, R6, #-1
+    STR R3, R6, #0
+    ADD R6, R6, #-1
+    STR R4, R6, #0
+    ADD R6, R6, #-1
+    STR R5, R6, #0
+    ; R1 points at the current pair of characters
+    ADD R1, R0, #0
+PUTSP_LOOP
+    LDR R2, R1, #0
+    LD R3, LOW_BYTE
+    AND R0, R2, R3
+    BRz PUTSP_DONE
+    OUT
+    ; shift the high byte down into R0, bit by bit.
+    ; R3 is the bit to set in R0, R4 is the bit to test in R2
+    AND R0, R0, #0
+    ADD R3, R0, #1
+    LD R4, HIGH_BYTE_BIT
+PUTSP_SHIFT
+    AND R5, R2, R4
+    BRz PUTSP_SKIP
+    ADD R0, R0, R3
+PUTSP_SKIP
+    ADD R3, R3, R3
+    ADD R4, R4, R4
+    ; R4 becomes zero once it shifts past bit 15
+    BRnp PUTSP_SHIFT
+    ADD R0, R0, #0
+    BRz PUTSP_DONE
+    OUT
+    ADD R1, R1, #1
+    BRnzp PUTSP_LOOP
+PUTSP_DONE
+    LDR R5, R6, #0
+    ADD R6, R6, #1
+    LDR R4, R6, #0
+    ADD R6, R6, #1
+    LDR R3, R6, #0
+    ADD R6, R6, #1
+    LDR R2, R6, #0
+    ADD R6, R6, #1
+    LDR R1, R6, #0
+    ADD R6, R6, #1
+    LDR R0, R6, #0
+    ADD R6, R6, #1
+    RTI
+LOW_BYTE        .fill x00FF
+HIGH_BYTE_BIT   .fill x0100
+
+; device register addresses
+KBSR_ADDR   .fill xFE00
+KBDR_ADDR   .fill xFE02
+DSR_ADDR    .fill xFE04
+DDR_ADDR    .fill xFE06
+
+; HALT, prints the halting message and clears the clock enable bit of the MCR.
+; The machine stops halfway through, at the STI to the MCR, in supervisor mode with the trap's
+; PSR and PC on the supervisor stack. The value stored is R6, the supervisor stack pointer: it
+; points into system memory so its bit 15 is clear, and no register the program sees changes.
+HALT_ROUTINE
+    ST R0, HALT_SAVED_R0
+    LEA R0, HALT_MSG
+    PUTS
+    LD R0, HALT_SAVED_R0
+    STI R6, MCR_ADDR    ; this stops the machine
+    ; if the clock is ever started again, return to the program
+    RTI
+MCR_ADDR        .fill xFFFE
+HALT_SAVED_R0   .fill x0000
+HALT_MSG        .stringz "\n\n--- Halting the LC-3 ---\n\n"
+
+.end
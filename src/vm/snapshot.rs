@@ -0,0 +1,262 @@
+use std::io::{self, Read, Write};
+
+use crate::vm::instructions::Register;
+use crate::vm::interrupts::InterruptRequest;
+use crate::vm::machine::{Machine, PrivilegeMode};
+use crate::vm::memory::{Memory, MemoryInit};
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"LC3SNAP\0";
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+// Snapshot layout (version 1), all numbers big endian:
+//   magic "LC3SNAP\0", version u16
+//   R0-R7 (R6 as currently aliased), ssp, usp: i16 each, register mode: u8
+//   ip: u16, psr: u16, halted: u8, instruction count: u64
+//   protect system memory: u8, protect device memory: u8
+//   memory init: u8 (0 zero, 1 pattern, 2 random), parameter: u64
+//   pending interrupt count: u16, then per interrupt: vector u8, priority u8
+//   protected range count: u16, then per range: start u16, end u16
+//   region count: u32, then per region: start u16, length u32, words i16 * length
+//   device count: u32, then per device: name length u32, name, state length u32, state
+impl Machine {
+    pub fn save_snapshot(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        write_u16(&mut writer, SNAPSHOT_VERSION)?;
+
+        for i in 0..8u8 {
+            write_u16(&mut writer, self.registers.get(i.into()) as u16)?;
+        }
+        write_u16(&mut writer, self.registers.ssp() as u16)?;
+        write_u16(&mut writer, self.registers.usp() as u16)?;
+        writer.write_all(&[self.registers.mode.is_supervisor() as u8])?;
+
+        write_u16(&mut writer, self.ip)?;
+        write_u16(&mut writer, self.encode_psr())?;
+        writer.write_all(&[self.halted as u8])?;
+        writer.write_all(&self.instruction_count.to_be_bytes())?;
+
+        writer.write_all(&[
+            self.protect_system_memory as u8,
+            self.protect_device_memory as u8,
+        ])?;
+
+        let (kind, parameter) = match self.memory.init() {
+            MemoryInit::Zero => (0, 0),
+            MemoryInit::Pattern(value) => (1, value as u16 as u64),
+            MemoryInit::Random(seed) => (2, seed),
+        };
+        writer.write_all(&[kind])?;
+        writer.write_all(&parameter.to_be_bytes())?;
+
+        write_u16(&mut writer, self.pending_interrupts.len() as u16)?;
+        for request in &self.pending_interrupts {
+            writer.write_all(&[request.vector, request.priority])?;
+        }
+
+        write_u16(&mut writer, self.protected_ranges.len() as u16)?;
+        for range in &self.protected_ranges {
+            write_u16(&mut writer, *range.start())?;
+            write_u16(&mut writer, *range.end())?;
+        }
+
+        let regions: Vec<(u16, &[i16])> = self.memory.regions().collect();
+        write_u32(&mut writer, regions.len() as u32)?;
+        for (start, words) in regions {
+            write_u16(&mut writer, start)?;
+            write_u32(&mut writer, words.len() as u32)?;
+            for word in words {
+                write_u16(&mut writer, *word as u16)?;
+            }
+        }
+
+        write_u32(&mut writer, self.devices.len() as u32)?;
+        for device in &self.devices {
+            write_bytes(&mut writer, device.name().as_bytes())?;
+            write_bytes(&mut writer, &device.save_state())?;
+        }
+
+        Ok(())
+    }
+
+    // Restores a snapshot into this machine. Devices are not created from the snapshot,
+    // the ones already attached get their saved state back (matched by name, in order).
+    pub fn load_snapshot(&mut self, mut reader: impl Read) -> io::Result<()> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(invalid_data("not an LC-3 snapshot"));
+        }
+
+        let version = read_u16(&mut reader)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported snapshot version {version}"
+            )));
+        }
+
+        let mut registers = [0i16; 8];
+        for register in &mut registers {
+            *register = read_u16(&mut reader)? as i16;
+        }
+        let ssp = read_u16(&mut reader)? as i16;
+        let usp = read_u16(&mut reader)? as i16;
+        let mode = if read_u8(&mut reader)? != 0 {
+            PrivilegeMode::Supervisor
+        } else {
+            PrivilegeMode::User
+        };
+
+        let ip = read_u16(&mut reader)?;
+        let psr = read_u16(&mut reader)?;
+        if !matches!(psr & 0b111, 0b100 | 0b010 | 0b001) {
+            return Err(invalid_data("invalid condition code in saved PSR"));
+        }
+        let halted = read_u8(&mut reader)? != 0;
+
+        let instruction_count = read_u64(&mut reader)?;
+
+        let protect_system_memory = read_u8(&mut reader)? != 0;
+        let protect_device_memory = read_u8(&mut reader)? != 0;
+
+        let kind = read_u8(&mut reader)?;
+        let parameter = read_u64(&mut reader)?;
+        let init = match kind {
+            0 => MemoryInit::Zero,
+            1 => MemoryInit::Pattern(parameter as i16),
+            2 => MemoryInit::Random(parameter),
+            _ => return Err(invalid_data("invalid memory init")),
+        };
+
+        let mut pending_interrupts = Vec::new();
+        for _ in 0..read_u16(&mut reader)? {
+            pending_interrupts.push(InterruptRequest {
+                vector: read_u8(&mut reader)?,
+                priority: read_u8(&mut reader)? & 0b111,
+            });
+        }
+
+        let mut protected_ranges = Vec::new();
+        for _ in 0..read_u16(&mut reader)? {
+            let start = read_u16(&mut reader)?;
+            let end = read_u16(&mut reader)?;
+            protected_ranges.push(start..=end);
+        }
+
+        // untouched words aren't saved, the init policy brings them back
+        let mut memory = Memory::with_init(init);
+        for _ in 0..read_u32(&mut reader)? {
+            let start = read_u16(&mut reader)?;
+            let length = read_u32(&mut reader)?;
+            if start as u32 + length > 1 << 16 {
+                return Err(invalid_data("memory region out of bounds"));
+            }
+
+            for i in 0..length {
+                memory[start + i as u16] = read_u16(&mut reader)? as i16;
+            }
+        }
+
+        let mut device_states = Vec::new();
+        for _ in 0..read_u32(&mut reader)? {
+            let name = String::from_utf8(read_bytes(&mut reader)?)
+                .map_err(|_| invalid_data("invalid device name"))?;
+            let state = read_bytes(&mut reader)?;
+            device_states.push((name, state));
+        }
+
+        // everything was read successfully, now apply it
+        self.decode_psr(psr);
+        self.registers.mode = mode;
+        self.registers.set_ssp(ssp);
+        self.registers.set_usp(usp);
+        for (i, value) in registers.into_iter().enumerate() {
+            let register = Register::from(i as u8);
+            if register != Register::R6 {
+                *self.registers.get_mut(register) = value;
+            }
+        }
+
+        self.ip = ip;
+        self.halted = halted;
+        self.instruction_count = instruction_count;
+        self.protect_system_memory = protect_system_memory;
+        self.protect_device_memory = protect_device_memory;
+        self.protected_ranges = protected_ranges;
+        self.memory = memory;
+        self.pending_interrupts = pending_interrupts;
+
+        // the recorded steps no longer lead back from this state
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        // every register was just written, memory counts by the restored touched map
+        self.reset_init_checks();
+
+        for device in &mut self.devices {
+            let position = device_states
+                .iter()
+                .position(|(name, _)| name == device.name());
+
+            if let Some(position) = position {
+                let (_, state) = device_states.remove(position);
+                device.load_state(&state);
+            }
+        }
+
+        self.rewind_devices();
+
+        Ok(())
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_u16(writer: &mut impl Write, value: u16) -> io::Result<()> {
+    writer.write_all(&value.to_be_bytes())
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_be_bytes())
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_u32(writer, bytes.len() as u32)?;
+    writer.write_all(bytes)
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+// the buffer only grows as the data arrives, a corrupt length can't allocate gigabytes up front
+fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let length = read_u32(reader)? as u64;
+    let mut buf = Vec::new();
+    reader.by_ref().take(length).read_to_end(&mut buf)?;
+    if buf.len() as u64 != length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
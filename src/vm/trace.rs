@@ -0,0 +1,238 @@
+use std::io::{self, Write};
+
+use crate::vm::instructions::{Instruction, Register};
+use crate::vm::machine::{ConditionCode, Machine, PrivilegeMode};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    // one line per instruction, fields separated by " | "
+    Text,
+
+    // one JSON object per line
+    JsonLines,
+}
+
+// What a single executed instruction did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub word: u16,
+    pub instruction: Instruction,
+
+    // values after the instruction
+    pub registers_written: Vec<(Register, i16)>,
+    pub memory_reads: Vec<(u16, i16)>,
+    pub memory_writes: Vec<(u16, i16)>,
+    pub condition_code: ConditionCode,
+    pub privilege: PrivilegeMode,
+}
+
+impl TraceRecord {
+    pub fn write_text(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(
+            writer,
+            "x{:04X} | x{:04X} | {} |",
+            self.pc, self.word, self.instruction
+        )?;
+
+        for (register, value) in &self.registers_written {
+            write!(writer, " R{}=x{:04X}", *register as u8, *value as u16)?;
+        }
+        write!(writer, " |")?;
+
+        for (address, value) in &self.memory_reads {
+            write!(writer, " r x{address:04X}=x{:04X}", *value as u16)?;
+        }
+        for (address, value) in &self.memory_writes {
+            write!(writer, " w x{address:04X}=x{:04X}", *value as u16)?;
+        }
+
+        writeln!(
+            writer,
+            " | {} | {}",
+            condition_code_name(self.condition_code),
+            privilege_name(self.privilege)
+        )
+    }
+
+    pub fn write_json(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(
+            writer,
+            "{{\"pc\":{},\"word\":{},\"disassembly\":\"{}\",\"registers\":{{",
+            self.pc, self.word, self.instruction
+        )?;
+
+        for (i, (register, value)) in self.registers_written.iter().enumerate() {
+            let comma = if i == 0 { "" } else { "," };
+            write!(writer, "{comma}\"R{}\":{value}", *register as u8)?;
+        }
+
+        write!(writer, "}},\"reads\":")?;
+        write_accesses(&mut writer, &self.memory_reads)?;
+        write!(writer, ",\"writes\":")?;
+        write_accesses(&mut writer, &self.memory_writes)?;
+
+        writeln!(
+            writer,
+            ",\"cc\":\"{}\",\"privilege\":\"{}\"}}",
+            condition_code_name(self.condition_code),
+            privilege_name(self.privilege)
+        )
+    }
+}
+
+fn write_accesses(writer: &mut impl Write, accesses: &[(u16, i16)]) -> io::Result<()> {
+    write!(writer, "[")?;
+    for (i, (address, value)) in accesses.iter().enumerate() {
+        let comma = if i == 0 { "" } else { "," };
+        write!(writer, "{comma}{{\"address\":{address},\"value\":{value}}}")?;
+    }
+    write!(writer, "]")
+}
+
+fn condition_code_name(condition_code: ConditionCode) -> &'static str {
+    match condition_code {
+        ConditionCode::Negative => "N",
+        ConditionCode::Zero => "Z",
+        ConditionCode::Positive => "P",
+    }
+}
+
+fn privilege_name(privilege: PrivilegeMode) -> &'static str {
+    match privilege {
+        PrivilegeMode::Supervisor => "supervisor",
+        PrivilegeMode::User => "user",
+    }
+}
+
+pub struct Tracer {
+    sink: Box<dyn Write + Send>,
+    format: TraceFormat,
+
+    // the record of the instruction being executed
+    current: Option<TraceRecord>,
+    registers_before: [i16; 8],
+    // supervisor and user stack pointers
+    stack_pointers_before: (i16, i16),
+
+    // writing stops at the first error, `stop_trace` reports it
+    error: Option<io::Error>,
+}
+
+impl Machine {
+    pub fn start_trace(&mut self, sink: impl Write + Send + 'static, format: TraceFormat) {
+        self.tracer = Some(Tracer {
+            sink: Box::new(sink),
+            format,
+            current: None,
+            registers_before: [0; 8],
+            stack_pointers_before: (0, 0),
+            error: None,
+        });
+    }
+
+    // flushes the sink, returning the first error writing to it if there was one
+    pub fn stop_trace(&mut self) -> io::Result<()> {
+        let Some(mut tracer) = self.tracer.take() else {
+            return Ok(());
+        };
+
+        match tracer.error {
+            Some(err) => Err(err),
+            None => tracer.sink.flush(),
+        }
+    }
+
+    pub(crate) fn trace_begin(&mut self, word: u16, instruction: Instruction) {
+        let registers_before = std::array::from_fn(|i| self.registers.get((i as u8).into()));
+        let stack_pointers_before = (self.registers.ssp(), self.registers.usp());
+        let pc = self.executing_pc;
+
+        let Some(tracer) = &mut self.tracer else {
+            return;
+        };
+
+        tracer.registers_before = registers_before;
+        tracer.stack_pointers_before = stack_pointers_before;
+        tracer.current = Some(TraceRecord {
+            pc,
+            word,
+            instruction,
+            registers_written: Vec::new(),
+            memory_reads: Vec::new(),
+            memory_writes: Vec::new(),
+            condition_code: self.condition_code,
+            privilege: self.privilege,
+        });
+    }
+
+    pub(crate) fn trace_read(&mut self, address: u16, value: i16) {
+        if let Some(record) = self.tracer.as_mut().and_then(|t| t.current.as_mut()) {
+            record.memory_reads.push((address, value));
+        }
+    }
+
+    pub(crate) fn trace_write(&mut self, address: u16, value: i16) {
+        if let Some(record) = self.tracer.as_mut().and_then(|t| t.current.as_mut()) {
+            record.memory_writes.push((address, value));
+        }
+    }
+
+    pub(crate) fn trace_end(&mut self) {
+        let registers_after: [i16; 8] =
+            std::array::from_fn(|i| self.registers.get((i as u8).into()));
+        let stack_pointers_after = (self.registers.ssp(), self.registers.usp());
+
+        let Some(tracer) = &mut self.tracer else {
+            return;
+        };
+        let Some(mut record) = tracer.current.take() else {
+            return;
+        };
+
+        // R6 is compared through the stack pointers themselves, so switching between USP and SSP
+        // isn't a write. the pushes and pops of a TRAP, RTI or exception aren't either
+        let stack_switch = matches!(
+            record.instruction,
+            Instruction::Trap(_) | Instruction::ReturnFromInterrupt
+        ) || record.privilege != self.privilege;
+        let stack_pointer_written =
+            !stack_switch && tracer.stack_pointers_before != stack_pointers_after;
+
+        // the destination register counts even if its value didn't change
+        let destination = record.instruction.destination_register();
+        for (i, (before, after)) in tracer
+            .registers_before
+            .iter()
+            .zip(registers_after)
+            .enumerate()
+        {
+            let register = Register::from(i as u8);
+            let changed = if register == Register::R6 {
+                stack_pointer_written
+            } else {
+                *before != after
+            };
+
+            if changed || destination == Some(register) {
+                record.registers_written.push((register, after));
+            }
+        }
+
+        record.condition_code = self.condition_code;
+        record.privilege = self.privilege;
+
+        if tracer.error.is_some() {
+            return;
+        }
+
+        let result = match tracer.format {
+            TraceFormat::Text => record.write_text(&mut tracer.sink),
+            TraceFormat::JsonLines => record.write_json(&mut tracer.sink),
+        };
+
+        if let Err(err) = result {
+            tracer.error = Some(err);
+        }
+    }
+}